@@ -0,0 +1,309 @@
+use image::GenericImageView;
+use na::Vector3;
+
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::lighting::Color;
+
+// What rays that don't hit anything see
+#[derive(Debug)]
+pub enum Background {
+    Solid(Color),
+    // Blend from bottom (looking straight down) to top (looking straight up)
+    Gradient {
+        bottom: Color,
+        top: Color,
+    },
+    Sky(Sky),
+    Image(EnvironmentMap),
+}
+
+impl Background {
+    // direction is unit length
+    pub fn color(&self, direction: &Vector3<f32>) -> Color {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let t = 0.5 + 0.5 * direction.y;
+                *bottom * (1.0 - t) + *top * t
+            },
+            Background::Sky(sky) => sky.color(direction),
+            Background::Image(map) => map.color(direction),
+        }
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Solid(Color::white())
+    }
+}
+
+// Clear daytime sky using the Preetham model ("A Practical Analytic Model for Daylight", 1999),
+// scaled so the sky straight up is about intensity bright, plus a disc for the sun
+#[derive(Debug, Clone)]
+pub struct Sky {
+    pub sun_direction: Vector3<f32>,    // Towards the sun, unit length
+    pub turbidity: f32,                 // Haziness, 2 is very clear and 10 is hazy
+    pub intensity: f32,
+    pub sun_intensity: f32,
+    pub sun_size: f32,                  // Angular radius of the sun in DEGREES
+    pub ground: Color,                  // Below the horizon
+}
+
+impl Sky {
+    pub fn color(&self, direction: &Vector3<f32>) -> Color {
+        if direction.y < 0.0 {
+            return self.ground
+        }
+
+        // Angle of the sun from straight up. The model breaks down once the sun sets.
+        let sun_theta = self.sun_direction.y.clamp(0.01, 1.0).acos();
+        let theta = direction.y.max(0.01).acos();   // Of the view direction from straight up
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();  // From the sun
+
+        let t = self.turbidity;
+        let perez_y = [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703];
+        let perez_x = [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452];
+        let perez_yc = [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529];
+
+        // Chromaticity straight up, the luminance is normalised away
+        let (s, s2, s3) = (sun_theta, sun_theta * sun_theta, sun_theta.powi(3));
+        let zenith_x = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s)
+            + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s + 0.00394)
+            + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s + 0.25886);
+        let zenith_y = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s)
+            + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s + 0.00516)
+            + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s + 0.26688);
+
+        // Each value relative to its value straight up
+        let relative = |coefficients: &[f32; 5]| perez(coefficients, theta, gamma)/perez(coefficients, 0.0, sun_theta);
+        let luminance = self.intensity * relative(&perez_y);
+        let x = zenith_x * relative(&perez_x);
+        let y = zenith_y * relative(&perez_yc);
+
+        let mut color = xyy_to_rgb(x, y, luminance);
+        if gamma.to_degrees() < self.sun_size {
+            color += Color::white() * self.sun_intensity;
+        }
+        color
+    }
+}
+
+// Distribution of light across the sky, theta from straight up and gamma from the sun
+fn perez(coefficients: &[f32; 5], theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    (1.0 + a * (b/theta.cos()).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+// CIE xyY to linear sRGB
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Color {
+    let big_x = x * luminance/y;
+    let big_z = (1.0 - x - y) * luminance/y;
+    Color::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    ).map(|c| c.max(0.0))
+}
+
+// 8 bit sRGB channel to linear light, 0 -> 1
+fn srgb_to_linear(value: u8) -> f32 {
+    let c = value as f32/255.0;
+    if c <= 0.04045 {
+        c/12.92
+    } else {
+        ((c + 0.055)/1.055).powf(2.4)
+    }
+}
+
+// Panorama image wrapped around the scene, looked up by direction. The middle of the image is
+// straight ahead (-Z), the top edge is straight up.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,     // Row order
+    pub intensity: f32,     // Brightness multiplier
+    pub rotation: f32,      // Turns the image around the vertical axis, in DEGREES
+}
+
+impl EnvironmentMap {
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> EnvironmentMap {
+        assert_eq!(pixels.len(), (width * height) as usize, "environment map needs one color per pixel");
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            intensity: 1.0,
+            rotation: 0.0,
+        }
+    }
+
+    // Radiance .hdr files keep their full range. Any other format the image crate reads is taken to
+    // be sRGB encoded, and is decoded to linear 0 -> 1.
+    pub fn load<P: AsRef<Path>>(path: P) -> image::ImageResult<EnvironmentMap> {
+        let is_hdr = path.as_ref().extension().is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"));
+        if is_hdr {
+            return EnvironmentMap::load_hdr(path)
+        }
+
+        let image = image::open(path)?;
+        let (width, height) = image.dimensions();
+        let pixels = image.to_rgb8().pixels()
+            .map(|p| Color::new(srgb_to_linear(p[0]), srgb_to_linear(p[1]), srgb_to_linear(p[2])))
+            .collect();
+        Ok(EnvironmentMap::new(width, height, pixels))
+    }
+
+    fn load_hdr<P: AsRef<Path>>(path: P) -> image::ImageResult<EnvironmentMap> {
+        let decoder = image::hdr::HdrDecoder::new(BufReader::new(File::open(path)?))?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?.iter()
+            .map(|p| Color::new(p[0], p[1], p[2]))
+            .collect();
+        Ok(EnvironmentMap::new(metadata.width, metadata.height, pixels))
+    }
+
+    pub fn color(&self, direction: &Vector3<f32>) -> Color {
+        let longitude = direction.x.atan2(-direction.z) + self.rotation.to_radians();
+        let u = (0.5 + longitude/(2.0 * PI)).rem_euclid(1.0);
+        let v = direction.y.clamp(-1.0, 1.0).acos()/PI;
+        self.sample(u, v) * self.intensity
+    }
+
+    // Bilinear lookup, u and v from 0 -> 1. Wraps around horizontally.
+    fn sample(&self, u: f32, v: f32) -> Color {
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let pixel = |px: i64, py: i64| {
+            let px = px.rem_euclid(self.width as i64) as u32;
+            let py = py.clamp(0, self.height as i64 - 1) as u32;
+            self.pixels[(py * self.width + px) as usize]
+        };
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1, y0) * fx;
+        let bottom = pixel(x0, y0 + 1) * (1.0 - fx) + pixel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    // Half the size in each direction (down to 1 pixel), each pixel the average of 4
+    fn downsample(&self) -> EnvironmentMap {
+        let (width, height) = ((self.width/2).max(1), (self.height/2).max(1));
+        let pixel = |x: u32, y: u32| self.pixels[(y.min(self.height - 1) * self.width + x.min(self.width - 1)) as usize];
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| (pixel(2 * x, 2 * y) + pixel(2 * x + 1, 2 * y) + pixel(2 * x, 2 * y + 1) + pixel(2 * x + 1, 2 * y + 1))/4.0)
+            .collect();
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            ..self.clone()
+        }
+    }
+
+    // Unit direction through the middle of a pixel, ignoring rotation
+    fn pixel_direction(&self, x: u32, y: u32) -> Vector3<f32> {
+        let longitude = ((x as f32 + 0.5)/self.width as f32 - 0.5) * 2.0 * PI;
+        let theta = (y as f32 + 0.5)/self.height as f32 * PI;   // From straight up
+        Vector3::new(theta.sin() * longitude.sin(), theta.cos(), -theta.sin() * longitude.cos())
+    }
+}
+
+// Lighting from an environment map, as if it were infinitely far away all around the scene.
+// Blurred copies of the map are worked out up front so shading only needs a few lookups.
+#[derive(Debug)]
+pub struct EnvironmentLighting {
+    irradiance: EnvironmentMap,     // Diffuse light reaching a surface facing each way, divided by pi
+    levels: Vec<EnvironmentMap>,    // Blurrier and smaller each level, for rougher reflections
+    pub shadow_samples: u32,        // Rays used to find how much of the environment a point can see
+}
+
+const LIGHTING_MAP_WIDTH: u32 = 256;    // Biggest blurred copy, detail beyond this is lost in the blur anyway
+const IRRADIANCE_MAP_WIDTH: u32 = 32;
+
+impl EnvironmentLighting {
+    // intensity scales the light on top of the map's own intensity
+    pub fn new(map: &EnvironmentMap, intensity: f32, shadow_samples: u32) -> EnvironmentLighting {
+        let mut base = EnvironmentMap {
+            intensity: map.intensity * intensity,
+            ..map.clone()
+        };
+        while base.width > LIGHTING_MAP_WIDTH {
+            base = base.downsample();
+        }
+
+        let mut levels = vec![base];
+        while levels[levels.len() - 1].width > 1 {
+            let next = levels[levels.len() - 1].downsample();
+            levels.push(next);
+        }
+
+        // Add up the light from every direction, weighted by how face on it is and by the solid
+        // angle of each pixel (smaller towards the poles)
+        let source = levels.iter().find(|level| level.width <= 2 * IRRADIANCE_MAP_WIDTH).unwrap_or(&levels[0]);
+        let mut irradiance = EnvironmentMap {
+            width: IRRADIANCE_MAP_WIDTH,
+            height: IRRADIANCE_MAP_WIDTH/2,
+            pixels: Vec::new(),
+            ..source.clone()
+        };
+        let pixel_angle = (2.0 * PI/source.width as f32) * (PI/source.height as f32);
+        irradiance.pixels = (0..irradiance.height)
+            .flat_map(|y| (0..irradiance.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let normal = irradiance.pixel_direction(x, y);
+                let mut total = Color::black();
+                for sy in 0..source.height {
+                    let theta = (sy as f32 + 0.5)/source.height as f32 * PI;
+                    for sx in 0..source.width {
+                        let cos = normal.dot(&source.pixel_direction(sx, sy));
+                        if cos > 0.0 {
+                            total += source.pixels[(sy * source.width + sx) as usize] * (cos * theta.sin() * pixel_angle);
+                        }
+                    }
+                }
+                total/PI
+            })
+            .collect();
+
+        EnvironmentLighting {
+            irradiance,
+            levels,
+            shadow_samples,
+        }
+    }
+
+    // Diffuse light reflected by a white surface facing along normal, with nothing in the way
+    pub fn irradiance(&self, normal: &Vector3<f32>) -> Color {
+        self.irradiance.color(normal)
+    }
+
+    // Light arriving from around direction, blurred more the rougher (0 -> 1) the surface is
+    pub fn radiance(&self, direction: &Vector3<f32>, roughness: f32) -> Color {
+        let level = roughness.clamp(0.0, 1.0) * (self.levels.len() - 1) as f32;
+        let (lower, t) = (level.floor() as usize, level.fract());
+        let upper = (lower + 1).min(self.levels.len() - 1);
+        self.levels[lower].color(direction) * (1.0 - t) + self.levels[upper].color(direction) * t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_is_decoded_to_linear() {
+        assert_eq!(srgb_to_linear(0), 0.0);
+        assert!((srgb_to_linear(255) - 1.0).abs() < 1.0e-6);
+        // Mid grey in sRGB is only about a fifth as bright in linear light
+        assert!((srgb_to_linear(128) - 0.2158).abs() < 1.0e-3);
+    }
+}
@@ -0,0 +1,110 @@
+use na::{Point3, Vector3, Isometry3};
+use serde::Deserialize;
+
+// Which side of the image the field of view spans
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum FovAxis {
+    Vertical,
+    Horizontal,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    Perspective {
+        fov: f32,   // In DEGREES
+        fov_axis: FovAxis,
+    },
+    // All rays are parallel to the view direction
+    Orthographic {
+        height: f32,    // Height of the view in world units, width follows from the aspect ratio
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,    // Point the camera looks at
+    pub up: Vector3<f32>,       // Roughly upwards on screen, doesn't need to be perpendicular to the view
+    pub projection: Projection,
+}
+
+impl Camera {
+    // Perspective camera with a vertical field of view
+    pub fn new(position: Point3<f32>, target: Point3<f32>, up: Vector3<f32>, fov: f32) -> Camera {
+        Camera {
+            position,
+            target,
+            up,
+            projection: Projection::Perspective {
+                fov,
+                fov_axis: FovAxis::Vertical,
+            },
+        }
+    }
+
+    pub fn orthographic(position: Point3<f32>, target: Point3<f32>, up: Vector3<f32>, height: f32) -> Camera {
+        Camera {
+            position,
+            target,
+            up,
+            projection: Projection::Orthographic {
+                height,
+            },
+        }
+    }
+
+    pub fn look_at(&mut self, target: Point3<f32>) {
+        self.target = target;
+    }
+
+    // Move the camera, keeping the direction it is facing
+    pub fn translate(&mut self, d_pos: &Vector3<f32>) {
+        self.position += d_pos;
+        self.target += d_pos;
+    }
+
+    // Switches to a perspective projection if the camera was orthographic
+    pub fn set_fov(&mut self, fov: f32) {
+        let fov_axis = match self.projection {
+            Projection::Perspective { fov_axis, .. } => fov_axis,
+            Projection::Orthographic { .. } => FovAxis::Vertical,
+        };
+        self.projection = Projection::Perspective { fov, fov_axis };
+    }
+
+    pub fn direction(&self) -> Vector3<f32> {
+        (self.target - self.position).normalize()
+    }
+
+    // Transform from view space (camera at the origin looking down -Z) to world space
+    pub fn view_to_world(&self) -> Isometry3<f32> {
+        Isometry3::look_at_rh(&self.position, &self.target, &self.up).inverse()
+    }
+
+    // Origin and direction in view space of the ray through a point on the screen.
+    // ndc_x and ndc_y go from -1 to 1, with +y upwards. aspect is width/height.
+    pub fn view_space_ray(&self, ndc_x: f32, ndc_y: f32, aspect: f32) -> (Point3<f32>, Vector3<f32>) {
+        match self.projection {
+            Projection::Perspective { fov, fov_axis } => {
+                // Half the height of the image plane at distance 1 from the camera
+                let half_height = match fov_axis {
+                    FovAxis::Vertical => (fov.to_radians()/2.0).tan(),
+                    FovAxis::Horizontal => (fov.to_radians()/2.0).tan()/aspect,
+                };
+                let direction = Vector3::new(ndc_x * half_height * aspect, ndc_y * half_height, -1.0);
+                (Point3::origin(), direction.normalize())
+            },
+            Projection::Orthographic { height } => {
+                let half_height = height/2.0;
+                (Point3::new(ndc_x * half_height * aspect, ndc_y * half_height, 0.0), -Vector3::z())
+            },
+        }
+    }
+}
+
+impl Default for Camera {
+    // At the origin looking down -Z
+    fn default() -> Self {
+        Camera::new(Point3::origin(), Point3::new(0.0, 0.0, -1.0), Vector3::y(), 90.0)
+    }
+}
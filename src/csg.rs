@@ -0,0 +1,384 @@
+// Constructive solid geometry: objects built by combining the distance fields of two others.
+// Smooth versions use the polynomial smooth min/max from https://iquilezles.org/articles/smin/,
+// where radius is roughly how far the blend reaches from where the two surfaces meet. Their
+// normals come from the slope of the blended field (the default Object::get_normal).
+
+use na::{Point3, Vector3};
+
+use crate::objects::Object;
+use crate::material::Material;
+
+// Both objects
+pub struct Union {
+    pub a: Box<dyn Object>,
+    pub b: Box<dyn Object>,
+}
+
+impl Union {
+    pub fn new<A: Object + 'static, B: Object + 'static>(a: A, b: B) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+
+    // The surface nearest to point belongs to whichever object is closer
+    fn closest(&self, point: &Point3<f32>) -> &dyn Object {
+        if self.a.distance_estimate(point) <= self.b.distance_estimate(point) {
+            self.a.as_ref()
+        } else {
+            self.b.as_ref()
+        }
+    }
+}
+
+impl Object for Union {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        self.a.distance_estimate(point).min(self.b.distance_estimate(point))
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        self.closest(point).get_normal(point)
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        self.a.get_material_ref()
+    }
+
+    fn get_material(&self, point: &Point3<f32>) -> Material {
+        self.closest(point).get_material(point)
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "Union"
+    }
+}
+
+// Only where the objects overlap
+pub struct Intersection {
+    pub a: Box<dyn Object>,
+    pub b: Box<dyn Object>,
+}
+
+impl Intersection {
+    pub fn new<A: Object + 'static, B: Object + 'static>(a: A, b: B) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+
+    // The boundary is made of whichever object's surface is further out
+    fn furthest(&self, point: &Point3<f32>) -> &dyn Object {
+        if self.a.distance_estimate(point) >= self.b.distance_estimate(point) {
+            self.a.as_ref()
+        } else {
+            self.b.as_ref()
+        }
+    }
+}
+
+impl Object for Intersection {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        self.a.distance_estimate(point).max(self.b.distance_estimate(point))
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        self.furthest(point).get_normal(point)
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        self.a.get_material_ref()
+    }
+
+    fn get_material(&self, point: &Point3<f32>) -> Material {
+        self.furthest(point).get_material(point)
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "Intersection"
+    }
+}
+
+// a with b carved out of it. The carved faces take b's material.
+pub struct Difference {
+    pub a: Box<dyn Object>,
+    pub b: Box<dyn Object>,
+}
+
+impl Difference {
+    pub fn new<A: Object + 'static, B: Object + 'static>(a: A, b: B) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+
+    // True if the surface at point is the inside of b
+    fn on_carved_face(&self, point: &Point3<f32>) -> bool {
+        -self.b.distance_estimate(point) > self.a.distance_estimate(point)
+    }
+}
+
+impl Object for Difference {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        self.a.distance_estimate(point).max(-self.b.distance_estimate(point))
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        if self.on_carved_face(point) {
+            -self.b.get_normal(point)   // Faces into b
+        } else {
+            self.a.get_normal(point)
+        }
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        self.a.get_material_ref()
+    }
+
+    fn get_material(&self, point: &Point3<f32>) -> Material {
+        if self.on_carved_face(point) {
+            self.b.get_material(point)
+        } else {
+            self.a.get_material(point)
+        }
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "Difference"
+    }
+}
+
+// Union that melts the two objects together where they meet
+pub struct SmoothUnion {
+    pub a: Box<dyn Object>,
+    pub b: Box<dyn Object>,
+    pub radius: f32,
+}
+
+impl SmoothUnion {
+    pub fn new<A: Object + 'static, B: Object + 'static>(a: A, b: B, radius: f32) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+            radius,
+        }
+    }
+
+    // (distance, how much of a is in the blend from 0 -> 1)
+    fn blend(&self, point: &Point3<f32>) -> (f32, f32) {
+        let (da, db) = (self.a.distance_estimate(point), self.b.distance_estimate(point));
+        let h = (0.5 + 0.5 * (db - da)/self.radius).clamp(0.0, 1.0);
+        (mix(db, da, h) - self.radius * h * (1.0 - h), h)
+    }
+}
+
+impl Object for SmoothUnion {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        self.blend(point).0
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        self.a.get_material_ref()
+    }
+
+    fn get_material(&self, point: &Point3<f32>) -> Material {
+        let h = self.blend(point).1;
+        self.b.get_material(point).mix(&self.a.get_material(point), h)
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "SmoothUnion"
+    }
+}
+
+// Intersection with the edge where the surfaces cross rounded off
+pub struct SmoothIntersection {
+    pub a: Box<dyn Object>,
+    pub b: Box<dyn Object>,
+    pub radius: f32,
+}
+
+impl SmoothIntersection {
+    pub fn new<A: Object + 'static, B: Object + 'static>(a: A, b: B, radius: f32) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+            radius,
+        }
+    }
+
+    // (distance, how much of a is in the blend from 0 -> 1)
+    fn blend(&self, point: &Point3<f32>) -> (f32, f32) {
+        let (da, db) = (self.a.distance_estimate(point), self.b.distance_estimate(point));
+        let h = (0.5 - 0.5 * (db - da)/self.radius).clamp(0.0, 1.0);
+        (mix(db, da, h) + self.radius * h * (1.0 - h), h)
+    }
+}
+
+impl Object for SmoothIntersection {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        self.blend(point).0
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        self.a.get_material_ref()
+    }
+
+    fn get_material(&self, point: &Point3<f32>) -> Material {
+        let h = self.blend(point).1;
+        self.b.get_material(point).mix(&self.a.get_material(point), h)
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "SmoothIntersection"
+    }
+}
+
+// a with b carved out of it, with the edge of the cut rounded off
+pub struct SmoothDifference {
+    pub a: Box<dyn Object>,
+    pub b: Box<dyn Object>,
+    pub radius: f32,
+}
+
+impl SmoothDifference {
+    pub fn new<A: Object + 'static, B: Object + 'static>(a: A, b: B, radius: f32) -> Self {
+        Self {
+            a: Box::new(a),
+            b: Box::new(b),
+            radius,
+        }
+    }
+
+    // (distance, how much of the carved face is in the blend from 0 -> 1)
+    fn blend(&self, point: &Point3<f32>) -> (f32, f32) {
+        let (da, db) = (self.a.distance_estimate(point), self.b.distance_estimate(point));
+        let h = (0.5 - 0.5 * (da + db)/self.radius).clamp(0.0, 1.0);
+        (mix(da, -db, h) + self.radius * h * (1.0 - h), h)
+    }
+}
+
+impl Object for SmoothDifference {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        self.blend(point).0
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        self.a.get_material_ref()
+    }
+
+    fn get_material(&self, point: &Point3<f32>) -> Material {
+        let h = self.blend(point).1;
+        self.a.get_material(point).mix(&self.b.get_material(point), h)
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "SmoothDifference"
+    }
+}
+
+// Linear interpolation, t = 0 gives x and t = 1 gives y
+#[inline]
+fn mix(x: f32, y: f32, t: f32) -> f32 {
+    x * (1.0 - t) + y * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lighting::Color;
+    use crate::objects::Sphere;
+
+    const TOLERANCE: f32 = 1.0e-4;
+
+    fn sphere(centre: [f32; 3], radius: f32, albedo: Color) -> Sphere {
+        Sphere { centre: Point3::from(centre), radius, material: Material::diffuse(albedo) }
+    }
+
+    fn red() -> Color {
+        Color::new(1.0, 0.0, 0.0)
+    }
+
+    fn blue() -> Color {
+        Color::new(0.0, 0.0, 1.0)
+    }
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!((value - expected).abs() < TOLERANCE, "expected {}, got {}", expected, value);
+    }
+
+    #[test]
+    fn difference_distances() {
+        // Unit sphere with a bite taken out of its +x side
+        let difference = Difference::new(sphere([0.0, 0.0, 0.0], 1.0, red()), sphere([1.0, 0.0, 0.0], 0.5, blue()));
+        assert_close(difference.distance_estimate(&Point3::new(-2.0, 0.0, 0.0)), 1.0);
+        assert_close(difference.distance_estimate(&Point3::new(-0.5, 0.0, 0.0)), -0.5);
+        // Inside the bite is outside the object
+        assert_close(difference.distance_estimate(&Point3::new(0.9, 0.0, 0.0)), 0.4);
+        assert_close(difference.distance_estimate(&Point3::new(0.5, 0.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn difference_carved_face() {
+        let difference = Difference::new(sphere([0.0, 0.0, 0.0], 1.0, red()), sphere([1.0, 0.0, 0.0], 0.5, blue()));
+
+        // Bottom of the bite faces back out through it, and takes b's material
+        let carved = Point3::new(0.5, 0.0, 0.0);
+        let normal = difference.get_normal(&carved);
+        assert!((normal - Vector3::new(1.0, 0.0, 0.0)).norm() < TOLERANCE, "{:?}", normal);
+        assert_eq!(difference.get_material(&carved).albedo, blue());
+
+        // The rest of the surface is a's
+        let outside = Point3::new(-1.0, 0.0, 0.0);
+        let normal = difference.get_normal(&outside);
+        assert!((normal - Vector3::new(-1.0, 0.0, 0.0)).norm() < TOLERANCE, "{:?}", normal);
+        assert_eq!(difference.get_material(&outside).albedo, red());
+    }
+
+    #[test]
+    fn union_and_intersection_materials() {
+        let (a, b) = (|| sphere([-0.5, 0.0, 0.0], 1.0, red()), || sphere([0.5, 0.0, 0.0], 1.0, blue()));
+        let union = Union::new(a(), b());
+        assert_eq!(union.get_material(&Point3::new(-1.5, 0.0, 0.0)).albedo, red());
+        assert_eq!(union.get_material(&Point3::new(1.5, 0.0, 0.0)).albedo, blue());
+
+        // The left edge of the overlap is b's surface, and the right edge is a's
+        let intersection = Intersection::new(a(), b());
+        assert_close(intersection.distance_estimate(&Point3::new(-0.5, 0.0, 0.0)), 0.0);
+        assert_eq!(intersection.get_material(&Point3::new(-0.5, 0.0, 0.0)).albedo, blue());
+        assert_eq!(intersection.get_material(&Point3::new(0.5, 0.0, 0.0)).albedo, red());
+    }
+
+    #[test]
+    fn smooth_union_matches_union_away_from_blend() {
+        let union = Union::new(sphere([-2.0, 0.0, 0.0], 1.0, red()), sphere([2.0, 0.0, 0.0], 1.0, blue()));
+        let smooth = SmoothUnion::new(sphere([-2.0, 0.0, 0.0], 1.0, red()), sphere([2.0, 0.0, 0.0], 1.0, blue()), 0.5);
+
+        // The distances to the two spheres differ by more than the radius at all of these
+        for point in [[-3.5, 0.0, 0.0], [-2.0, 1.5, 0.0], [2.0, 0.0, -1.2], [3.0, 0.0, 0.0]] {
+            let point = Point3::from(point);
+            assert_close(smooth.distance_estimate(&point), union.distance_estimate(&point));
+            assert_eq!(smooth.get_material(&point), union.get_material(&point));
+        }
+
+        // Halfway between them the blend pulls the surface in, and mixes the materials evenly
+        let middle = Point3::origin();
+        assert!(smooth.distance_estimate(&middle) < union.distance_estimate(&middle));
+        assert_eq!(smooth.get_material(&middle).albedo, Color::new(0.5, 0.0, 0.5));
+    }
+
+    #[test]
+    fn smooth_intersection_and_difference_match_sharp_away_from_blend() {
+        let (a, b) = (|| sphere([0.0, 0.0, 0.0], 2.0, red()), || sphere([2.0, 0.0, 0.0], 1.0, blue()));
+        let (intersection, smooth_intersection) = (Intersection::new(a(), b()), SmoothIntersection::new(a(), b(), 0.25));
+        let (difference, smooth_difference) = (Difference::new(a(), b()), SmoothDifference::new(a(), b(), 0.25));
+
+        for point in [[-3.0, 0.0, 0.0], [0.0, 0.0, 0.0], [4.0, 0.0, 0.0]] {
+            let point = Point3::from(point);
+            assert_close(smooth_intersection.distance_estimate(&point), intersection.distance_estimate(&point));
+            assert_close(smooth_difference.distance_estimate(&point), difference.distance_estimate(&point));
+        }
+    }
+}
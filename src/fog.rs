@@ -0,0 +1,59 @@
+use na::{Point3, Vector3};
+use serde::Deserialize;
+
+use crate::lighting::Color;
+
+// Light scattered by the air between the camera and what it sees, blending colors towards the fog
+// color the further rays travel. Distances are in world units.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum Fog {
+    // No fog before start, completely fogged after end
+    Linear {
+        color: Color,
+        start: f32,
+        end: f32,
+    },
+    // Thins out smoothly, higher density gives thicker fog
+    Exponential {
+        color: Color,
+        density: f32,
+    },
+    // Like exponential, but clearer close up and a sharper fall off further away
+    ExponentialSquared {
+        color: Color,
+        density: f32,
+    },
+    // Exponential fog that is thickest at the height base (density there) and thins out going
+    // up, falloff is how quickly. Rays pointing up into the sky pass through less of it.
+    Height {
+        color: Color,
+        density: f32,
+        base: f32,
+        falloff: f32,
+    },
+}
+
+impl Fog {
+    // color seen through distance of fog, along a ray starting at origin going in direction
+    pub fn apply(&self, color: Color, origin: &Point3<f32>, direction: &Vector3<f32>, distance: f32) -> Color {
+        let (fog_color, amount) = match *self {
+            Fog::Linear { color, start, end } => (color, ((distance - start)/(end - start)).clamp(0.0, 1.0)),
+            Fog::Exponential { color, density } => (color, 1.0 - (-density * distance).exp()),
+            Fog::ExponentialSquared { color, density } => (color, 1.0 - (-(density * distance).powi(2)).exp()),
+            Fog::Height { color, density, base, falloff } => {
+                // Integral of density * exp(-falloff * (y - base)) along the ray, from
+                // https://iquilezles.org/articles/fog/
+                let start_density = density * (-falloff * (origin.y - base)).exp();
+                let climb = falloff * direction.y;
+                let optical_depth = if climb.abs() < 1.0e-5 {
+                    start_density * distance     // Level ray, density doesn't change
+                } else {
+                    start_density * (1.0 - (-climb * distance).exp())/climb
+                };
+                (color, 1.0 - (-optical_depth).exp())
+            },
+        };
+        color * (1.0 - amount) + fog_color * amount
+    }
+}
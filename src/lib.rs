@@ -0,0 +1,28 @@
+//! Raymarching renderer.
+//!
+//! A `Scene` is built from a list of `Object`s (anything with a distance estimate) and a list of
+//! `Light`s, either in code or from a RON scene file (see `scene_file`), then rendered into an
+//! image with `Scene::render`.
+
+extern crate nalgebra as na;
+
+pub mod camera;
+pub mod ray;
+pub mod objects;
+pub mod material;
+pub mod csg;
+pub mod transform;
+pub mod scene;
+pub mod lighting;
+pub mod scene_file;
+pub mod sampling;
+pub mod tone_mapping;
+pub mod fog;
+pub mod background;
+
+pub use scene::{Scene, RenderMode};
+pub use camera::{Camera, Projection};
+pub use objects::Object;
+pub use material::Material;
+pub use ray::Ray;
+pub use lighting::{Color, Light};
@@ -1,24 +1,312 @@
 use image::Rgba;
+use serde::Deserialize;
 use na::{Point3, Vector3};
 
-use std::ops::{Mul, MulAssign};
+use std::f32::consts::PI;
+use std::ops::{Add, AddAssign, Sub, Mul, MulAssign, Div, DivAssign};
+
+use crate::material::Material;
+use crate::sampling::Rng;
+
+// How light fades with distance from the light
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum Attenuation {
+    None,
+    Linear,         // intensity/distance
+    InverseSquare,  // intensity/distance^2, like real lights
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind {
+    // Shines equally in all directions from a point
+    Point {
+        pos: Point3<f32>,
+    },
+    // Infinitely far away, like the sun. All rays are parallel and it doesn't fade with distance.
+    Directional {
+        direction: Vector3<f32>,    // Direction the light travels in, unit length
+    },
+    // Point light only shining within a cone
+    Spot {
+        pos: Point3<f32>,
+        direction: Vector3<f32>,    // Centre of the cone, unit length
+        angle: f32,                 // Angle from the centre to the edge of the cone, in DEGREES
+        softness: f32,              // 0 -> 1, how much of the cone fades out towards the edge
+    },
+    // Light coming from everywhere, blending from the light's color above to ground_color below.
+    // Not blocked by anything.
+    Ambient {
+        ground_color: Color,
+    },
+}
+
+// How shadows cast by a light are worked out
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum ShadowMode {
+    // Either fully lit or fully in shadow
+    Hard,
+    // Cheap penumbra from how close the shadow ray passes to objects. Lower hardness gives softer shadows.
+    Soft {
+        hardness: f32,
+    },
+    // Light coming from a sphere, sampled with several shadow rays. Slower and noisy, but physically
+    // correct, so useful to compare Soft against. For directional lights radius is the angular
+    // radius in DEGREES (the sun is about 0.27).
+    Area {
+        radius: f32,
+        samples: u32,
+    },
+}
 
 #[derive(Debug)]
 pub struct Light {
-    pub pos: Point3<f32>,
-    pub luminance: f32,     // From 1 to 0
+    pub kind: LightKind,
+    pub color: Color,
+    pub intensity: f32,     // Brightness at a distance of 1, lights add together so this can be above 1
+    pub attenuation: Attenuation,   // Only used by point and spot lights
+    pub shadows: ShadowMode,
+}
+
+// Light reaching a point on a surface from one light
+pub struct Illumination {
+    pub direction: Vector3<f32>,    // From the point towards the light, unit length
+    pub distance: f32,              // How far away the light is, infinite for directional lights
+    pub radiance: Color,
 }
 
 impl Light {
-    pub fn new(pos: Point3<f32>, luminance: f32) -> Light {
+    // Point light that doesn't fade with distance
+    pub fn new(pos: Point3<f32>, color: Color, intensity: f32) -> Light {
+        Light::with_attenuation(pos, color, intensity, Attenuation::None)
+    }
+
+    pub fn with_attenuation(pos: Point3<f32>, color: Color, intensity: f32, attenuation: Attenuation) -> Light {
+        Light {
+            kind: LightKind::Point { pos },
+            color,
+            intensity,
+            attenuation,
+            shadows: ShadowMode::Hard,
+        }
+    }
+
+    pub fn directional(direction: Vector3<f32>, color: Color, intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Directional {
+                direction: direction.normalize(),
+            },
+            color,
+            intensity,
+            attenuation: Attenuation::None,
+            shadows: ShadowMode::Hard,
+        }
+    }
+
+    pub fn spot(pos: Point3<f32>, direction: Vector3<f32>, angle: f32, softness: f32, color: Color, intensity: f32, attenuation: Attenuation) -> Light {
         Light {
-            pos,
-            luminance,
+            kind: LightKind::Spot {
+                pos,
+                direction: direction.normalize(),
+                angle,
+                softness,
+            },
+            color,
+            intensity,
+            attenuation,
+            shadows: ShadowMode::Hard,
         }
     }
+
+    // Same color from every direction
+    pub fn ambient(color: Color, intensity: f32) -> Light {
+        Light::hemisphere(color, color, intensity)
+    }
+
+    // Sky colored from above, ground colored from below
+    pub fn hemisphere(sky_color: Color, ground_color: Color, intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Ambient { ground_color },
+            color: sky_color,
+            intensity,
+            attenuation: Attenuation::None,
+            shadows: ShadowMode::Hard,
+        }
+    }
+
+    // Light arriving at a point this far away
+    pub fn radiance(&self, distance: f32) -> Color {
+        let falloff = match self.attenuation {
+            Attenuation::None => 1.0,
+            Attenuation::Linear => 1.0/distance.max(1.0e-4),
+            Attenuation::InverseSquare => 1.0/(distance * distance).max(1.0e-4),
+        };
+        self.color * (self.intensity * falloff)
+    }
+
+    // Where light reaching point comes from and how bright it is, ignoring anything in the way.
+    // None for ambient lights, and for points outside a spot light's cone.
+    pub fn illuminate(&self, point: &Point3<f32>) -> Option<Illumination> {
+        match self.kind {
+            LightKind::Point { pos } => {
+                let to_light = pos - point;
+                let distance = to_light.norm();
+                Some(Illumination {
+                    direction: to_light/distance,
+                    distance,
+                    radiance: self.radiance(distance),
+                })
+            },
+            LightKind::Directional { direction } => Some(Illumination {
+                direction: -direction,
+                distance: f32::INFINITY,
+                radiance: self.color * self.intensity,
+            }),
+            LightKind::Spot { pos, direction, angle, softness } => {
+                let to_light = pos - point;
+                let distance = to_light.norm();
+                let cos_angle = (-to_light/distance).dot(&direction);
+
+                // Fade from the inner part of the cone to the edge
+                let cos_outer = angle.to_radians().cos();
+                let cos_inner = (angle * (1.0 - softness)).to_radians().cos();
+                let cone = smoothstep(cos_outer, cos_inner, cos_angle);
+                if cone <= 0.0 {
+                    return None
+                }
+
+                Some(Illumination {
+                    direction: to_light/distance,
+                    distance,
+                    radiance: self.radiance(distance) * cone,
+                })
+            },
+            LightKind::Ambient { .. } => None,
+        }
+    }
+
+    // Random direction and distance towards somewhere on an area light, as seen from point.
+    // radius is from ShadowMode::Area.
+    pub fn sample_area(&self, point: &Point3<f32>, radius: f32, rng: &mut Rng) -> (Vector3<f32>, f32) {
+        match self.kind {
+            LightKind::Point { pos } | LightKind::Spot { pos, .. } => {
+                let to_sample = (pos + rng.in_unit_sphere() * radius) - point;
+                let distance = to_sample.norm();
+                (to_sample/distance, distance)
+            },
+            LightKind::Directional { direction } => {
+                // Tilt the direction by up to the angular radius
+                let spread = radius.to_radians().tan();
+                ((-direction + rng.in_unit_sphere() * spread).normalize(), f32::INFINITY)
+            },
+            LightKind::Ambient { .. } => (Vector3::y(), f32::INFINITY),
+        }
+    }
+
+    // Light from an ambient light reaching a surface facing along normal. Black for other lights.
+    pub fn ambient_radiance(&self, normal: &Vector3<f32>) -> Color {
+        match self.kind {
+            LightKind::Ambient { ground_color } => {
+                let up = 0.5 + 0.5 * normal.y;  // 1 facing straight up, 0 facing straight down
+                (self.color * up + ground_color * (1.0 - up)) * self.intensity
+            },
+            _ => Color::black(),
+        }
+    }
+}
+
+// 0 below edge0, 1 above edge1, smooth in between
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x >= edge1 { 1.0 } else { 0.0 }  // Hard edge
+    }
+    let t = ((x - edge0)/(edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
 }
 
-#[derive(Debug, Copy, Clone)]
+// Darkens ambient light in creases and corners, by looking at how close other surfaces are along
+// the normal. From https://iquilezles.org/articles/nvscene2008/rwwtt.pdf
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmbientOcclusion {
+    pub steps: u32,         // Number of points sampled along the normal
+    pub distance: f32,      // How far from the surface the last sample is
+    pub strength: f32,      // 0 turns it off, higher gives darker creases
+}
+
+impl AmbientOcclusion {
+    // 0 -> 1, how much ambient light reaches point. distance_estimate is for the whole scene.
+    pub fn visibility<F: Fn(&Point3<f32>) -> f32>(&self, point: &Point3<f32>, normal: &Vector3<f32>, distance_estimate: F) -> f32 {
+        let mut occlusion = 0.0;
+        let mut total_weight = 0.0;
+        let mut weight = 1.0;
+
+        for i in 1..=self.steps {
+            let h = self.distance * i as f32/self.steps as f32;
+            // With nothing nearby the closest surface is the one we started on, h away
+            let d = distance_estimate(&(point + normal * h));
+            occlusion += weight * ((h - d)/h).max(0.0);
+            total_weight += weight;
+            weight *= 0.5;      // Samples near the surface matter most
+        }
+
+        if total_weight == 0.0 {
+            return 1.0
+        }
+        (1.0 - self.strength * occlusion/total_weight).clamp(0.0, 1.0)
+    }
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        AmbientOcclusion {
+            steps: 5,
+            distance: 0.5,
+            strength: 1.0,
+        }
+    }
+}
+
+// How highlights are worked out
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum ShadingModel {
+    BlinnPhong,
+    // Microfacet model (GGX distribution, Smith-Schlick shadowing, Schlick fresnel), more realistic
+    // highlights for a little more work
+    CookTorrance,
+}
+
+impl ShadingModel {
+    // Specular light reflected towards the eye, per unit of incoming light along the normal.
+    // All vectors point away from the surface and are unit length.
+    pub fn specular(&self, normal: &Vector3<f32>, to_light: &Vector3<f32>, to_eye: &Vector3<f32>, material: &Material) -> Color {
+        let halfway = (to_light + to_eye).normalize();
+        let n_dot_h = normal.dot(&halfway).max(0.0);
+
+        match self {
+            ShadingModel::BlinnPhong => material.specular * n_dot_h.powf(material.shininess),
+            ShadingModel::CookTorrance => {
+                let n_dot_l = normal.dot(to_light).max(1.0e-4);
+                let n_dot_v = normal.dot(to_eye).max(1.0e-4);
+                let roughness = material.roughness();
+
+                let a2 = roughness.powi(4);
+                let distribution = a2/(PI * (n_dot_h * n_dot_h * (a2 - 1.0) + 1.0).powi(2));
+                let k = (roughness + 1.0).powi(2)/8.0;
+                let geometry = (n_dot_l/(n_dot_l * (1.0 - k) + k)) * (n_dot_v/(n_dot_v * (1.0 - k) + k));
+                // Schlick, with the specular color as the reflectance straight on
+                let fresnel_weight = (1.0 - halfway.dot(to_eye).max(0.0)).powi(5);
+                let fresnel = material.specular * (1.0 - fresnel_weight) + Color::white() * fresnel_weight;
+
+                fresnel * (distribution * geometry/(4.0 * n_dot_l * n_dot_v))
+            },
+        }
+    }
+}
+
+// Linear light, not limited to 0 -> 1 so that bright lights can add up past white. It is only
+// brought into range when written to an image, see tone_mapping.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Color {
     pub r: f32,
     pub g: f32,
@@ -46,22 +334,25 @@ impl Color {
         Color::new(a, a, a)
     }
 
+    // Anything outside 0 -> 1 is clipped, so tone map first
     pub fn get_image_rgba(&self) -> Rgba<u8> {
+        let quantize = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
         Rgba([
-            (self.r * 255.0).ceil() as u8,
-            (self.g * 255.0).ceil() as u8,
-            (self.b * 255.0).ceil() as u8,
+            quantize(self.r),
+            quantize(self.g),
+            quantize(self.b),
             255,
         ])
     }
 
-    // Fog like affect
-    #[inline]
-    pub fn fade_due_to_render_distance(ray_col: &Color, distance_travelled: f32) -> Color {
-        use crate::ray::RAY_MAX_TRAVEL_DISTANCE;
+    // Perceived brightness
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
 
-        let fade_amount = distance_travelled/RAY_MAX_TRAVEL_DISTANCE;
-        *ray_col
+    // Apply f to each channel
+    pub fn map<F: Fn(f32) -> f32>(&self, f: F) -> Color {
+        Color::new(f(self.r), f(self.g), f(self.b))
     }
 
     pub fn is_black(&self) -> bool {
@@ -70,6 +361,24 @@ impl Color {
 }
 
 
+impl Add for Color {
+    type Output = Self;
+
+    fn add(self, rhs: Color) -> Color {
+        Color {
+            r: self.r + rhs.r,
+            g: self.g + rhs.g,
+            b: self.b + rhs.b,
+        }
+    }
+}
+
+impl AddAssign for Color {
+    fn add_assign(&mut self, rhs: Color) {
+        *self = (*self) + rhs;
+    }
+}
+
 impl Mul<&Color> for Color {
     type Output = Self;
 
@@ -89,22 +398,50 @@ impl MulAssign<&Color> for Color {
     }
 }
 
+impl Sub for Color {
+    type Output = Self;
+
+    fn sub(self, rhs: Color) -> Color {
+        Color {
+            r: self.r - rhs.r,
+            g: self.g - rhs.g,
+            b: self.b - rhs.b,
+        }
+    }
+}
+
 impl Mul<f32> for Color {
     type Output = Self;
 
     fn mul(self, rhs: f32) -> Self {
         Color {
-            r: (self.r * rhs).min(1.0),
-            g: (self.g * rhs).min(1.0),
-            b: (self.b * rhs).min(1.0),
+            r: self.r * rhs,
+            g: self.g * rhs,
+            b: self.b * rhs,
         }
     }
 }
 
 impl MulAssign<f32> for Color {
     fn mul_assign(&mut self, rhs: f32) {
-        self.r = (self.r * rhs).min(1.0);
-        self.g = (self.g * rhs).min(1.0);
-        self.b = (self.b * rhs).min(1.0);
+        *self = (*self) * rhs;
     }
-}
\ No newline at end of file
+}
+
+impl Div<f32> for Color {
+    type Output = Self;
+
+    fn div(self, rhs: f32) -> Self {
+        Color {
+            r: self.r / rhs,
+            g: self.g / rhs,
+            b: self.b / rhs,
+        }
+    }
+}
+
+impl DivAssign<f32> for Color {
+    fn div_assign(&mut self, rhs: f32) {
+        *self = (*self) / rhs;
+    }
+}
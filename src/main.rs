@@ -1,98 +1,229 @@
 extern crate nalgebra as na;
 
-mod ray;
-mod objects;
-mod scene;
-mod lighting;
+use na::Point3;
 
-use na::{Point3, Vector3, Matrix4};
-use image::DynamicImage;
+use std::fmt;
+use std::process;
+use std::str::FromStr;
+use std::time::Instant;
 
-use scene::Scene;
-use objects::*;
-use lighting::{Color, Light};
+use raytracer::{Scene, Camera, Material, RenderMode};
+use raytracer::objects::*;
+use raytracer::lighting::{Color, Light};
+use raytracer::sampling::{SamplePattern, Filter};
+use raytracer::tone_mapping::ToneMapping;
 
 const DIMS: (u32, u32) = (1920, 1080);
 
-struct MainState {
-    main_scene: Scene,
+const USAGE: &str = "\
+Usage: raytracer [OPTIONS]
+
+Options:
+  -s, --scene <FILE>            Scene description to render (RON). Uses the built in scene if omitted
+  -W, --width <PIXELS>          Image width, overrides the scene
+  -H, --height <PIXELS>         Image height, overrides the scene
+      --fov <DEGREES>           Field of view, overrides the scene (and its projection)
+  -o, --output <FILE>           Where to save the image [default: out.png]
+  -t, --threads <N>             Number of render threads, 0 for one per core [default: 0]
+      --samples <N>             Samples per pixel, overrides the scene
+      --sample-pattern <NAME>   Where samples go in a pixel: grid, rotated-grid, jittered or halton, overrides the scene
+      --filter <NAME>           How samples are weighted: box, tent, gaussian or mitchell, overrides the scene
+      --adaptive <THRESHOLD>    Only use all the samples on pixels differing from a neighbour by more than THRESHOLD (0 -> 1)
+      --max-reflections <N>     Number of times a ray can be reflected, overrides the scene
+      --throughput-cutoff <F>   Skip reflections adding less than this much to a pixel (0 -> 1), overrides the scene
+      --tone-mapping <MAPPING>  How bright colors are fitted into the image: clamp, reinhard or aces, overrides the scene
+      --exposure <STOPS>        Brighten (or darken, if negative) before tone mapping, overrides the scene
+      --ao-only                 Render only the ambient occlusion, to help tune it
+  -h, --help                    Print this message";
+
+#[derive(Debug, Default)]
+struct Options {
+    scene: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    fov: Option<f32>,
+    output: Option<String>,
+    threads: Option<usize>,
+    samples: Option<u32>,
+    sample_pattern: Option<SamplePattern>,
+    filter: Option<Filter>,
+    adaptive: Option<f32>,
+    max_reflections: Option<usize>,
+    throughput_cutoff: Option<f32>,
+    tone_mapping: Option<ToneMapping>,
+    exposure: Option<f32>,
+    ao_only: bool,
+    help: bool,
+}
+
+#[derive(Debug)]
+struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
-impl MainState {
-    fn new() -> MainState {
-        let mut main_scene = Scene::new(DIMS.0, DIMS.1, 90.0,
-            vec![
-                // Box::new(Sphere {
-                //     centre: Point3::new(0.0, 1.0, -0.1),
-                //     radius: 1.0,
-                //     color: Color {
-                //         r: 0.0,
-                //         g: 1.0,
-                //         b: 0.0,
-                //     },
-                // }),
-                // Box::new(Sphere {
-                //     centre: Point3::new(-2.0, -2.0, -7.0),
-                //     radius: 1.0,
-                //     color: Color {
-                //         r: 1.0,
-                //         g: 1.0,
-                //         b: 1.0,
-                //     },
-                // }),
-                // Box::new(Sphere {
-                //     centre: Point3::new(2.0, -2.0, 4.0),
-                //     radius: 1.0,
-                //     color: Color {
-                //         r: 0.0,
-                //         g: 1.0,
-                //         b: 0.0,
-                //     },
-                // }),
-                Box::new(AxisAlignedCube {
-                    centre: Point3::new(2.0, -1.5, -5.0),
-                    size: 1.0,
-                    color: Color {
-                        r: 1.0,
-                        g: 0.0,
-                        b: 0.0,
-                    },
-                }),
-
-                Box::new(HorizontalPlane {
-                    y: -6.0,
-                    color: Color {
-                        r: 0.0,
-                        g: 1.0,
-                        b: 0.0,
-                    },
-                }),
-            ],
-            vec![
-                Light::new(Point3::new(-4.0, 5.0, -3.0), 1.0), // 
-                Light::new(Point3::new(4.0, 5.0, -3.0), 1.0),
-            ],
-        );
-
-        MainState {
-            main_scene,
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, UsageError> {
+        let mut options = Options::default();
+
+        while let Some(arg) = args.next() {
+            // Accept both `--flag value` and `--flag=value`
+            let (flag, inline_value) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => (arg[..i].to_owned(), Some(arg[i+1..].to_owned())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || inline_value.clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| UsageError(format!("missing value for `{}`", flag)));
+
+            match flag.as_str() {
+                "-s" | "--scene" => options.scene = Some(value()?),
+                "-W" | "--width" => options.width = Some(parse_value(&flag, &value()?)?),
+                "-H" | "--height" => options.height = Some(parse_value(&flag, &value()?)?),
+                "--fov" => options.fov = Some(parse_value(&flag, &value()?)?),
+                "-o" | "--output" => options.output = Some(value()?),
+                "-t" | "--threads" => options.threads = Some(parse_value(&flag, &value()?)?),
+                "--samples" => options.samples = Some(parse_value(&flag, &value()?)?),
+                "--sample-pattern" => options.sample_pattern = Some(parse_value(&flag, &value()?)?),
+                "--filter" => options.filter = Some(parse_value(&flag, &value()?)?),
+                "--adaptive" => options.adaptive = Some(parse_value(&flag, &value()?)?),
+                "--max-reflections" => options.max_reflections = Some(parse_value(&flag, &value()?)?),
+                "--throughput-cutoff" => options.throughput_cutoff = Some(parse_value(&flag, &value()?)?),
+                "--tone-mapping" => options.tone_mapping = Some(parse_value(&flag, &value()?)?),
+                "--exposure" => options.exposure = Some(parse_value(&flag, &value()?)?),
+                "--ao-only" => options.ao_only = true,
+                "-h" | "--help" => options.help = true,
+                _ => return Err(UsageError(format!("unexpected argument `{}`", arg))),
+            }
         }
+
+        options.validate()?;
+        Ok(options)
     }
 
-    fn render(&self) -> DynamicImage {
-        self.main_scene.render()
+    fn validate(&self) -> Result<(), UsageError> {
+        if self.width == Some(0) || self.height == Some(0) {
+            return Err(UsageError("image dimensions must be non-zero".to_owned()))
+        }
+        if let Some(fov) = self.fov {
+            if !(fov > 0.0 && fov < 180.0) {
+                return Err(UsageError(format!("`--fov` must be between 0 and 180 degrees, got {}", fov)))
+            }
+        }
+        if let Some(threshold) = self.adaptive {
+            if threshold < 0.0 {
+                return Err(UsageError(format!("`--adaptive` must not be negative, got {}", threshold)))
+            }
+        }
+        if let Some(cutoff) = self.throughput_cutoff {
+            if !(0.0..=1.0).contains(&cutoff) {
+                return Err(UsageError(format!("`--throughput-cutoff` must be between 0 and 1, got {}", cutoff)))
+            }
+        }
+        if self.samples == Some(0) {
+            return Err(UsageError("`--samples` must be at least 1".to_owned()))
+        }
+        Ok(())
+    }
+
+    // Command line settings take priority over the scene's own
+    fn apply(&self, scene: &mut Scene) {
+        if self.width.is_some() || self.height.is_some() {
+            scene.set_dimensions(self.width.unwrap_or(scene.width), self.height.unwrap_or(scene.height));
+        }
+        if let Some(fov) = self.fov {
+            scene.camera.set_fov(fov);
+        }
+        if let Some(threads) = self.threads {
+            scene.threads = threads;
+        }
+        if let Some(samples) = self.samples {
+            scene.samples = samples;
+        }
+        if let Some(sample_pattern) = self.sample_pattern {
+            scene.sample_pattern = sample_pattern;
+        }
+        if let Some(filter) = self.filter {
+            scene.filter = filter;
+        }
+        if let Some(threshold) = self.adaptive {
+            scene.adaptive_threshold = Some(threshold);
+        }
+        if let Some(max_reflections) = self.max_reflections {
+            scene.max_reflections = max_reflections;
+        }
+        if let Some(cutoff) = self.throughput_cutoff {
+            scene.throughput_cutoff = cutoff;
+        }
+        if let Some(tone_mapping) = self.tone_mapping {
+            scene.tone_mapping = tone_mapping;
+        }
+        if let Some(exposure) = self.exposure {
+            scene.exposure = exposure;
+        }
+        if self.ao_only {
+            scene.render_mode = RenderMode::AmbientOcclusion;
+        }
     }
 }
 
+fn parse_value<T: FromStr>(flag: &str, value: &str) -> Result<T, UsageError> {
+    value.parse().map_err(|_| UsageError(format!("invalid value `{}` for `{}`", value, flag)))
+}
 
-fn main() {
-    use std::time::Instant;
+fn build_scene() -> Scene {
+    let mut scene = Scene::new(DIMS.0, DIMS.1, Camera::default(), vec![], vec![]);
+
+    scene.add_object(AxisAlignedCube {
+        centre: Point3::new(2.0, -1.5, -5.0),
+        size: 1.0,
+        material: Material::diffuse(Color::new(1.0, 0.0, 0.0)),
+    });
+    scene.add_object(HorizontalPlane {
+        y: -6.0,
+        material: Material::diffuse(Color::new(0.0, 1.0, 0.0)),
+    });
 
-    let state = MainState::new();
+    scene.add_light(Light::new(Point3::new(-4.0, 5.0, -3.0), Color::white(), 0.5));
+    scene.add_light(Light::new(Point3::new(4.0, 5.0, -3.0), Color::white(), 0.5));
+
+    scene
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let mut scene = match &options.scene {
+        Some(path) => Scene::load(path).map_err(|e| format!("could not load scene `{}`: {}", path, e))?,
+        None => build_scene(),
+    };
+    options.apply(&mut scene);
 
     let render_start_time = Instant::now();
-    let image = state.render();
+    let image = scene.render();
     println!("Time taken: {:?}", Instant::now().duration_since(render_start_time));
 
-    image.save("out.png").unwrap();
-}
\ No newline at end of file
+    let output = options.output.as_deref().unwrap_or("out.png");
+    image.save(output).map_err(|e| format!("could not save image to `{}`: {}", output, e))
+}
+
+fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    if let Err(e) = run(options) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,98 @@
+use serde::Deserialize;
+
+use crate::lighting::Color;
+
+// How a surface looks. Every field has a default, so scene files only need the ones they change.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Material {
+    pub albedo: Color,          // Diffuse color
+    pub specular: Color,        // Color of highlights
+    pub shininess: f32,         // Specular exponent, higher gives smaller sharper highlights
+    pub reflectivity: f32,      // 0 -> 1, how much of the surface acts like a mirror
+    pub emission: Color,        // Light given off by the surface itself
+    pub transparency: f32,      // 0 -> 1, how much light passes through the surface
+    pub ior: f32,               // Index of refraction, for transparent materials
+    pub casts_shadows: bool,    // False lets light pass through to things behind it
+    pub receives_shadows: bool, // False lights the surface as if nothing was in the way
+}
+
+impl Material {
+    // Plain matte surface
+    pub fn diffuse(albedo: Color) -> Material {
+        Material {
+            albedo,
+            ..Material::default()
+        }
+    }
+
+    // Diffuse with white highlights
+    pub fn glossy(albedo: Color, shininess: f32) -> Material {
+        Material {
+            albedo,
+            specular: Color::white(),
+            shininess,
+            ..Material::default()
+        }
+    }
+
+    pub fn mirror(albedo: Color, reflectivity: f32) -> Material {
+        Material {
+            albedo,
+            reflectivity,
+            ..Material::default()
+        }
+    }
+
+    // Clear and see-through, ior is about 1.5 for glass and 1.33 for water
+    pub fn glass(ior: f32) -> Material {
+        Material {
+            specular: Color::white(),
+            shininess: 256.0,
+            transparency: 1.0,
+            ior,
+            ..Material::default()
+        }
+    }
+
+    // 0 -> 1, how blurry highlights and reflections are. Roughly matches the size of the Blinn-Phong
+    // highlight for the same shininess.
+    pub fn roughness(&self) -> f32 {
+        (2.0/(self.shininess + 2.0)).sqrt()
+    }
+
+    // Linear interpolation, t = 0 gives self and t = 1 gives other. Used for blending objects together.
+    pub fn mix(&self, other: &Material, t: f32) -> Material {
+        let mix = |x: f32, y: f32| x * (1.0 - t) + y * t;
+        let mix_colors = |x: &Color, y: &Color| Color::new(mix(x.r, y.r), mix(x.g, y.g), mix(x.b, y.b));
+
+        Material {
+            albedo: mix_colors(&self.albedo, &other.albedo),
+            specular: mix_colors(&self.specular, &other.specular),
+            shininess: mix(self.shininess, other.shininess),
+            reflectivity: mix(self.reflectivity, other.reflectivity),
+            emission: mix_colors(&self.emission, &other.emission),
+            transparency: mix(self.transparency, other.transparency),
+            ior: mix(self.ior, other.ior),
+            // Flags can't be blended, so take whichever material there is more of
+            casts_shadows: if t < 0.5 { self.casts_shadows } else { other.casts_shadows },
+            receives_shadows: if t < 0.5 { self.receives_shadows } else { other.receives_shadows },
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            albedo: Color::white(),
+            specular: Color::black(),     // No highlights unless asked for
+            shininess: 32.0,
+            reflectivity: 0.0,
+            emission: Color::black(),
+            transparency: 0.0,
+            ior: 1.0,
+            casts_shadows: true,
+            receives_shadows: true,
+        }
+    }
+}
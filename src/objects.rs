@@ -1,24 +1,36 @@
-use na::{Point3, Vector3};
+use na::{Point3, Vector2, Vector3};
 
-use crate::ray::Ray;
-use crate::lighting::Color;
+use crate::material::Material;
 
-pub trait Object {
+pub const NORMAL_EPSILON: f32 = 1.0e-4;    // Step used when estimating normals from the distance field
+
+// Send + Sync so scenes can be rendered from several threads at once
+pub trait Object: Send + Sync {
     fn distance_estimate(&self, point: &Point3<f32>) -> f32;
-    // returns closest surface normal
-    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32>;
-    fn get_color_ref(&self) -> &Color;
-    fn get_type_name(&self) -> &'static str;
-    fn get_reflectance(&self) -> f32 {
-        0.0
+    // returns closest surface normal. By default this is the slope of the distance field, override
+    // it if the normal can be worked out directly.
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        tetrahedron_normal(self, point, self.normal_epsilon())
+    }
+    // Step used by the default get_normal. Smaller picks up finer detail, but gets noisy. It is set
+    // per type by overriding this, there's no way to change it for a single object or from a scene
+    // file.
+    fn normal_epsilon(&self) -> f32 {
+        NORMAL_EPSILON
+    }
+    fn get_material_ref(&self) -> &Material;
+    // Material of the surface nearest to point, for objects that aren't made of a single material
+    fn get_material(&self, _point: &Point3<f32>) -> Material {
+        *self.get_material_ref()
     }
+    fn get_type_name(&self) -> &'static str;
 }
 
 #[derive(Debug)]
 pub struct Sphere {
     pub centre: Point3<f32>,
     pub radius: f32,
-    pub color: Color,
+    pub material: Material,
 }
 
 impl Object for Sphere {
@@ -34,57 +46,46 @@ impl Object for Sphere {
         (point - self.centre).normalize()
     }
 
-    fn get_color_ref(&self) -> &Color {
-        &self.color
+    fn get_material_ref(&self) -> &Material {
+        &self.material
     }
 
     fn get_type_name(&self) -> &'static str {
         "Sphere"
     }
-
-    fn get_reflectance(&self) -> f32 {
-        1.0
-    }
 }
 
 #[derive(Debug)]
 pub struct HorizontalPlane {
     pub y: f32,
-    pub color: Color,
+    pub material: Material,
 }
 
 impl Object for HorizontalPlane {
     fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
-        // Get cosine squared of angle to plane via dot product: j * r/|r| = cos(a) = 1 * r.y/r
-        let cos_ang_squared = (point.y).powi(2)/Vector3::new(point.x, point.y, point.z).norm_squared();
-        // sin^2 + cos^2 = 1 -> sin = sqrt(1 - cos^2)
-        // dy/sin(a) = distance
-        (point.y - self.y).abs()/(1.0 - cos_ang_squared).sqrt()
+        // Height above the plane, negative below it
+        point.y - self.y
     }
 
     // Simple upwards vector
-    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+    fn get_normal(&self, _point: &Point3<f32>) -> Vector3<f32> {
         Vector3::new(0.0, 1.0, 0.0)
     }
 
-    fn get_color_ref(&self) -> &Color {
-        &self.color
+    fn get_material_ref(&self) -> &Material {
+        &self.material
     }
 
     fn get_type_name(&self) -> &'static str {
         "HorizontalPlane"
     }
-
-    fn get_reflectance(&self) -> f32 {  // Don't want plane to be relfective
-        0.0
-    }
 }
 
 // Cube with no rotation
 pub struct AxisAlignedCube {
     pub centre: Point3<f32>,
     pub size: f32,   // width, height and depth
-    pub color: Color,
+    pub material: Material,
 }
 
 impl Object for AxisAlignedCube {
@@ -92,28 +93,676 @@ impl Object for AxisAlignedCube {
         let diff = point - self.centre;
 
         // Looked at stack overflow for this one https://math.stackexchange.com/questions/2133217/minimal-distance-to-a-cube-in-2d-and-3d-from-a-point-lying-outside
-        (
+        let outside = (
             0.0f32.max(diff.x.abs() - self.size).powi(2) +
             0.0f32.max(diff.y.abs() - self.size).powi(2) +
             0.0f32.max(diff.z.abs() - self.size).powi(2)
-        ).sqrt()
+        ).sqrt();
+        // Negative inside, distance to the nearest face. Needed for CSG to carve cubes.
+        let inside = (diff.x.abs() - self.size).max(diff.y.abs() - self.size).max(diff.z.abs() - self.size).min(0.0);
+        outside + inside
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        box_normal(&(point - self.centre), &Vector3::repeat(self.size))
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "AxisAlignedCube"
+    }
+}
+
+// Box with a different size along each axis
+#[derive(Debug)]
+pub struct Cuboid {
+    pub centre: Point3<f32>,
+    pub half_extents: Vector3<f32>,     // Half the width, height and depth
+    pub material: Material,
+}
+
+impl Object for Cuboid {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        box_distance(&(point - self.centre), &self.half_extents)
     }
 
     fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
-        // Normal vector will just be the inverse of line from centre to point, aligned with axis
-        let norm_misaligned = (point - self.centre).normalize();
-        // Algin to largest axis.
-        let max_axis_index = norm_misaligned.iamax();
-        let mut aligned = Vector3::zeros();
-        aligned[max_axis_index] = 1.0;
-        aligned
+        box_normal(&(point - self.centre), &self.half_extents)
     }
 
-    fn get_color_ref(&self) -> &Color {
-        &self.color
+    fn get_material_ref(&self) -> &Material {
+        &self.material
     }
 
     fn get_type_name(&self) -> &'static str {
         "Cuboid"
     }
-}
\ No newline at end of file
+}
+
+// Cuboid with its edges and corners rounded off, the outside still fits inside half_extents
+#[derive(Debug)]
+pub struct RoundedCuboid {
+    pub centre: Point3<f32>,
+    pub half_extents: Vector3<f32>,
+    pub radius: f32,    // Radius of the rounded edges, no bigger than the smallest half extent
+    pub material: Material,
+}
+
+impl RoundedCuboid {
+    fn inner_half_extents(&self) -> Vector3<f32> {
+        self.half_extents.map(|e| e - self.radius)
+    }
+}
+
+impl Object for RoundedCuboid {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        // Everything within radius of a smaller box
+        box_distance(&(point - self.centre), &self.inner_half_extents()) - self.radius
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        box_normal(&(point - self.centre), &self.inner_half_extents())
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "RoundedCuboid"
+    }
+}
+
+// Ring lying flat in the xz plane
+#[derive(Debug)]
+pub struct Torus {
+    pub centre: Point3<f32>,
+    pub major_radius: f32,  // From the centre to the middle of the tube
+    pub minor_radius: f32,  // Radius of the tube
+    pub material: Material,
+}
+
+impl Torus {
+    // Position relative to the circle running through the middle of the tube, in (radial, y)
+    fn profile(&self, point: &Point3<f32>) -> Vector2<f32> {
+        let offset = point - self.centre;
+        Vector2::new(Vector2::new(offset.x, offset.z).norm() - self.major_radius, offset.y)
+    }
+}
+
+impl Object for Torus {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        self.profile(point).norm() - self.minor_radius
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        profile_normal(&(point - self.centre), &self.profile(point).normalize())
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "Torus"
+    }
+}
+
+// Line segment from a to b with a radius around it
+#[derive(Debug)]
+pub struct Capsule {
+    pub a: Point3<f32>,
+    pub b: Point3<f32>,
+    pub radius: f32,
+    pub material: Material,
+}
+
+impl Capsule {
+    // Vector from the closest point on the segment to point
+    fn offset_from_segment(&self, point: &Point3<f32>) -> Vector3<f32> {
+        let pa = point - self.a;
+        let ba = self.b - self.a;
+        let len_squared = ba.norm_squared();
+        // How far along the segment the closest point is, 0 -> 1
+        let h = if len_squared > 0.0 {
+            (pa.dot(&ba)/len_squared).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        pa - ba * h
+    }
+}
+
+impl Object for Capsule {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        self.offset_from_segment(point).norm() - self.radius
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        self.offset_from_segment(point).normalize()
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "Capsule"
+    }
+}
+
+// Upright cylinder with flat ends, centred halfway up
+#[derive(Debug)]
+pub struct Cylinder {
+    pub centre: Point3<f32>,
+    pub radius: f32,
+    pub height: f32,
+    pub material: Material,
+}
+
+impl Cylinder {
+    // Distance outside the curved side and the flat ends, in (radial, y)
+    fn outside(&self, offset: &Vector3<f32>) -> Vector2<f32> {
+        Vector2::new(
+            Vector2::new(offset.x, offset.z).norm() - self.radius,
+            offset.y.abs() - self.height/2.0,
+        )
+    }
+}
+
+impl Object for Cylinder {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        let d = self.outside(&(point - self.centre));
+        d.x.max(d.y).min(0.0) + d.sup(&Vector2::zeros()).norm()
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        let offset = point - self.centre;
+        let d = self.outside(&offset);
+        let profile = if d.x > 0.0 && d.y > 0.0 {
+            // Nearest the rim
+            Vector2::new(d.x, d.y * offset.y.signum()).normalize()
+        } else if d.x > d.y {
+            Vector2::new(1.0, 0.0)
+        } else {
+            Vector2::new(0.0, offset.y.signum())
+        };
+        profile_normal(&offset, &profile)
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "Cylinder"
+    }
+}
+
+// Upright cylinder going on forever in both directions
+#[derive(Debug)]
+pub struct InfiniteCylinder {
+    pub centre: Point3<f32>,    // Any point on the axis
+    pub radius: f32,
+    pub material: Material,
+}
+
+impl Object for InfiniteCylinder {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        let offset = point - self.centre;
+        Vector2::new(offset.x, offset.z).norm() - self.radius
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        profile_normal(&(point - self.centre), &Vector2::new(1.0, 0.0))
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "InfiniteCylinder"
+    }
+}
+
+// Upright cone with its point at the top and a flat base height below it
+#[derive(Debug)]
+pub struct Cone {
+    pub apex: Point3<f32>,
+    pub radius: f32,    // Radius of the base
+    pub height: f32,
+    pub material: Material,
+}
+
+impl Cone {
+    // (vector from the closest point on the outline to point in (radial, y), +1 outside or -1 inside)
+    // From https://iquilezles.org/articles/distfunctions/
+    fn offset_from_outline(&self, point: &Point3<f32>) -> (Vector2<f32>, f32) {
+        let offset = point - self.apex;
+        let q = Vector2::new(self.radius, -self.height);     // Apex to the edge of the base
+        let w = Vector2::new(Vector2::new(offset.x, offset.z).norm(), offset.y);
+
+        let to_side = w - q * (w.dot(&q)/q.norm_squared()).clamp(0.0, 1.0);
+        let to_base = w - q.component_mul(&Vector2::new((w.x/q.x).clamp(0.0, 1.0), 1.0));
+        let sign = (-(w.x * q.y - w.y * q.x)).max(-(w.y - q.y)).signum();
+
+        if to_side.norm_squared() < to_base.norm_squared() {
+            (to_side, sign)
+        } else {
+            (to_base, sign)
+        }
+    }
+}
+
+impl Object for Cone {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        let (from_outline, sign) = self.offset_from_outline(point);
+        from_outline.norm() * sign
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        let (from_outline, sign) = self.offset_from_outline(point);
+        match from_outline.try_normalize(1.0e-6) {
+            Some(profile) => profile_normal(&(point - self.apex), &(profile * sign)),
+            None => tetrahedron_normal(self, point, self.normal_epsilon()),   // Exactly on the surface
+        }
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "Cone"
+    }
+}
+
+// Sphere stretched along each axis. The distance is a bound rather than exact.
+#[derive(Debug)]
+pub struct Ellipsoid {
+    pub centre: Point3<f32>,
+    pub radii: Vector3<f32>,
+    pub material: Material,
+}
+
+impl Object for Ellipsoid {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        // From https://iquilezles.org/articles/ellipsoids/
+        let offset = point - self.centre;
+        let k0 = offset.component_div(&self.radii).norm();
+        let k1 = offset.component_div(&self.radii.component_mul(&self.radii)).norm();
+        if k1 == 0.0 {
+            return -self.radii.min()     // At the centre
+        }
+        k0 * (k0 - 1.0)/k1
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        // Gradient of (x/a)^2 + (y/b)^2 + (z/c)^2
+        (point - self.centre).component_div(&self.radii.component_mul(&self.radii)).normalize()
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "Ellipsoid"
+    }
+}
+
+// Infinite plane facing any direction. Everything behind it counts as inside.
+#[derive(Debug)]
+pub struct Plane {
+    pub normal: Vector3<f32>,   // Must be unit length
+    pub distance: f32,          // Distance from the origin along the normal
+    pub material: Material,
+}
+
+impl Plane {
+    pub fn new(normal: Vector3<f32>, distance: f32, material: Material) -> Plane {
+        Plane {
+            normal: normal.normalize(),
+            distance,
+            material,
+        }
+    }
+}
+
+impl Object for Plane {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        point.coords.dot(&self.normal) - self.distance
+    }
+
+    fn get_normal(&self, _point: &Point3<f32>) -> Vector3<f32> {
+        self.normal
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "Plane"
+    }
+}
+
+// Flat triangle with no thickness, so the distance is never negative
+#[derive(Debug)]
+pub struct Triangle {
+    pub a: Point3<f32>,
+    pub b: Point3<f32>,
+    pub c: Point3<f32>,
+    pub material: Material,
+}
+
+impl Triangle {
+    // From Real-Time Collision Detection (Ericson), 5.1.5
+    fn closest_point(&self, p: &Point3<f32>) -> Point3<f32> {
+        let (a, b, c) = (self.a, self.b, self.c);
+        let ab = b - a;
+        let ac = c - a;
+
+        // Vertex regions
+        let ap = p - a;
+        let (d1, d2) = (ab.dot(&ap), ac.dot(&ap));
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return a
+        }
+        let bp = p - b;
+        let (d3, d4) = (ab.dot(&bp), ac.dot(&bp));
+        if d3 >= 0.0 && d4 <= d3 {
+            return b
+        }
+        let cp = p - c;
+        let (d5, d6) = (ab.dot(&cp), ac.dot(&cp));
+        if d6 >= 0.0 && d5 <= d6 {
+            return c
+        }
+
+        // Edge regions
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            return a + ab * (d1/(d1 - d3))
+        }
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            return a + ac * (d2/(d2 - d6))
+        }
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+            return b + (c - b) * ((d4 - d3)/((d4 - d3) + (d5 - d6)))
+        }
+
+        // Inside the face
+        let denom = 1.0/(va + vb + vc);
+        a + ab * (vb * denom) + ac * (vc * denom)
+    }
+}
+
+impl Object for Triangle {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        (point - self.closest_point(point)).norm()
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        // Points away from whichever side point is on
+        (point - self.closest_point(point))
+            .try_normalize(1.0e-6)
+            .unwrap_or_else(|| (self.b - self.a).cross(&(self.c - self.a)).normalize())
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "Triangle"
+    }
+}
+
+// Upright prism with a regular hexagon as its cross section
+#[derive(Debug)]
+pub struct HexagonalPrism {
+    pub centre: Point3<f32>,
+    pub radius: f32,    // From the centre to the middle of each side
+    pub height: f32,
+    pub material: Material,
+}
+
+impl Object for HexagonalPrism {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        // From https://iquilezles.org/articles/distfunctions/, with y as the long axis
+        let k = Vector3::new(-0.866_025_4, 0.5, 0.577_350_3);   // (-cos 30, sin 30, tan 30)
+        let offset = point - self.centre;
+
+        // Fold into one of the 12 symmetric slices of the hexagon
+        let mut p = Vector2::new(offset.x.abs(), offset.z.abs());
+        p -= Vector2::new(k.x, k.y) * (2.0 * Vector2::new(k.x, k.y).dot(&p).min(0.0));
+
+        let edge = Vector2::new(p.x.clamp(-k.z * self.radius, k.z * self.radius), self.radius);
+        let d = Vector2::new(
+            (p - edge).norm() * (p.y - self.radius).signum(),
+            offset.y.abs() - self.height/2.0,
+        );
+        d.x.max(d.y).min(0.0) + d.sup(&Vector2::zeros()).norm()
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        &self.material
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        "HexagonalPrism"
+    }
+}
+
+// Distance from a box centred on the origin, negative inside
+fn box_distance(offset: &Vector3<f32>, half_extents: &Vector3<f32>) -> f32 {
+    let q = offset.abs() - half_extents;
+    q.sup(&Vector3::zeros()).norm() + q.max().min(0.0)
+}
+
+fn box_normal(offset: &Vector3<f32>, half_extents: &Vector3<f32>) -> Vector3<f32> {
+    let q = offset.abs() - half_extents;
+    let outside = q.sup(&Vector3::zeros());
+    if outside.norm_squared() > 0.0 {
+        // Direction from the nearest point on the box
+        outside.zip_map(offset, |o, p| o * p.signum()).normalize()
+    } else {
+        // Inside, so use the face that is nearest
+        let axis = q.imax();
+        let mut normal = Vector3::zeros();
+        normal[axis] = offset[axis].signum();
+        normal
+    }
+}
+
+// For shapes that are the same all the way around the y axis. Turns a normal in (radial, y) into 3D.
+fn profile_normal(offset: &Vector3<f32>, profile: &Vector2<f32>) -> Vector3<f32> {
+    let radial = Vector3::new(offset.x, 0.0, offset.z)
+        .try_normalize(1.0e-6)
+        .unwrap_or_else(Vector3::x);    // On the axis, any direction will do
+    (radial * profile.x + Vector3::y() * profile.y).normalize()
+}
+
+// Normal from the slope of the distance field, sampling at the corners of a tetrahedron around point
+// so it only takes 4 distance estimates. From https://iquilezles.org/articles/normalsSDF/
+pub fn tetrahedron_normal<O: Object + ?Sized>(object: &O, point: &Point3<f32>, epsilon: f32) -> Vector3<f32> {
+    let corners = [
+        Vector3::new(1.0, -1.0, -1.0),
+        Vector3::new(-1.0, -1.0, 1.0),
+        Vector3::new(-1.0, 1.0, -1.0),
+        Vector3::new(1.0, 1.0, 1.0),
+    ];
+    corners.iter()
+        .map(|k| k * object.distance_estimate(&(point + k * epsilon)))
+        .sum::<Vector3<f32>>()
+        .normalize()
+}
+
+// So boxed objects (e.g. from scene files) can be wrapped by generic objects like Transformed
+impl<O: Object + ?Sized> Object for Box<O> {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        (**self).distance_estimate(point)
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        (**self).get_normal(point)
+    }
+
+    fn normal_epsilon(&self) -> f32 {
+        (**self).normal_epsilon()
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        (**self).get_material_ref()
+    }
+
+    fn get_material(&self, point: &Point3<f32>) -> Material {
+        (**self).get_material(point)
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        (**self).get_type_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 1.0e-4;
+
+    fn assert_distance<O: Object>(object: &O, point: [f32; 3], expected: f32) {
+        let distance = object.distance_estimate(&Point3::from(point));
+        assert!((distance - expected).abs() < TOLERANCE,
+            "{} at {:?}: expected {}, got {}", object.get_type_name(), point, expected, distance);
+    }
+
+    struct FineSphere(Sphere);
+
+    impl Object for FineSphere {
+        fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+            self.0.distance_estimate(point)
+        }
+
+        fn normal_epsilon(&self) -> f32 {
+            1.0e-6
+        }
+
+        fn get_material_ref(&self) -> &Material {
+            self.0.get_material_ref()
+        }
+
+        fn get_type_name(&self) -> &'static str {
+            "FineSphere"
+        }
+    }
+
+    #[test]
+    fn boxed_objects_keep_their_normal_epsilon() {
+        let sphere = Sphere { centre: Point3::origin(), radius: 1.0, material: Material::default() };
+        let boxed: Box<dyn Object> = Box::new(FineSphere(sphere));
+        assert_eq!(boxed.normal_epsilon(), 1.0e-6);
+    }
+
+    #[test]
+    fn cuboid_distances() {
+        let cuboid = Cuboid { centre: Point3::new(1.0, 0.0, 0.0), half_extents: Vector3::new(1.0, 2.0, 3.0), material: Material::default() };
+        assert_distance(&cuboid, [3.0, 0.0, 0.0], 1.0);
+        assert_distance(&cuboid, [3.0, 3.0, 0.0], 2.0_f32.sqrt());     // Off a corner
+        assert_distance(&cuboid, [1.0, 0.0, 0.0], -1.0);
+        assert_distance(&cuboid, [1.0, 2.0, 0.0], 0.0);
+    }
+
+    #[test]
+    fn rounded_cuboid_distances() {
+        let cuboid = RoundedCuboid { centre: Point3::origin(), half_extents: Vector3::new(1.0, 1.0, 1.0), radius: 0.5, material: Material::default() };
+        assert_distance(&cuboid, [2.0, 0.0, 0.0], 1.0);
+        assert_distance(&cuboid, [0.0, 0.0, 0.0], -1.0);
+        // The corner is rounded off, so it is further away than the corner of the unrounded box
+        assert_distance(&cuboid, [1.0, 1.0, 1.0], 3.0_f32.sqrt() * 0.5 - 0.5);
+    }
+
+    #[test]
+    fn torus_distances() {
+        let torus = Torus { centre: Point3::origin(), major_radius: 2.0, minor_radius: 0.5, material: Material::default() };
+        assert_distance(&torus, [2.0, 0.0, 0.0], -0.5);
+        assert_distance(&torus, [0.0, 0.0, 0.0], 1.5);   // In the hole
+        assert_distance(&torus, [0.0, 1.0, -2.0], 0.5);
+    }
+
+    #[test]
+    fn capsule_distances() {
+        let capsule = Capsule { a: Point3::new(0.0, -1.0, 0.0), b: Point3::new(0.0, 1.0, 0.0), radius: 0.5, material: Material::default() };
+        assert_distance(&capsule, [1.0, 0.0, 0.0], 0.5);
+        assert_distance(&capsule, [0.0, 3.0, 0.0], 1.5);     // Past the end
+        assert_distance(&capsule, [0.0, 0.5, 0.0], -0.5);
+    }
+
+    #[test]
+    fn cylinder_distances() {
+        let cylinder = Cylinder { centre: Point3::origin(), radius: 1.0, height: 2.0, material: Material::default() };
+        assert_distance(&cylinder, [2.0, 0.0, 0.0], 1.0);
+        assert_distance(&cylinder, [0.0, 3.0, 0.0], 2.0);
+        assert_distance(&cylinder, [2.0, 2.0, 0.0], 2.0_f32.sqrt());  // Off the rim
+        assert_distance(&cylinder, [0.0, 0.0, 0.0], -1.0);
+
+        let infinite = InfiniteCylinder { centre: Point3::origin(), radius: 1.0, material: Material::default() };
+        assert_distance(&infinite, [0.0, 100.0, 3.0], 2.0);
+        assert_distance(&infinite, [0.5, -100.0, 0.0], -0.5);
+    }
+
+    #[test]
+    fn cone_distances() {
+        let cone = Cone { apex: Point3::new(0.0, 1.0, 0.0), radius: 1.0, height: 1.0, material: Material::default() };
+        assert_distance(&cone, [0.0, 2.0, 0.0], 1.0);    // Above the point
+        assert_distance(&cone, [0.0, -1.0, 0.0], 1.0);   // Below the base
+        assert_distance(&cone, [1.0, 1.0, 0.0], 0.5_f32.sqrt());  // Out from the side
+        assert!(cone.distance_estimate(&Point3::new(0.0, 0.2, 0.0)) < 0.0);
+    }
+
+    #[test]
+    fn ellipsoid_distances() {
+        let ellipsoid = Ellipsoid { centre: Point3::origin(), radii: Vector3::new(1.0, 2.0, 3.0), material: Material::default() };
+        // Exact along the axes
+        assert_distance(&ellipsoid, [2.0, 0.0, 0.0], 1.0);
+        assert_distance(&ellipsoid, [0.0, 0.0, 3.0], 0.0);
+        assert!(ellipsoid.distance_estimate(&Point3::new(0.5, 1.0, 1.0)) < 0.0);
+        assert!(ellipsoid.distance_estimate(&Point3::origin()) < 0.0);
+    }
+
+    #[test]
+    fn plane_distances() {
+        let plane = Plane::new(Vector3::new(0.0, 2.0, 0.0), 1.0, Material::default());
+        assert_distance(&plane, [5.0, 3.0, -2.0], 2.0);
+        assert_distance(&plane, [0.0, -1.0, 0.0], -2.0);
+    }
+
+    #[test]
+    fn triangle_distances() {
+        let triangle = Triangle {
+            a: Point3::new(0.0, 0.0, 0.0),
+            b: Point3::new(2.0, 0.0, 0.0),
+            c: Point3::new(0.0, 2.0, 0.0),
+            material: Material::default(),
+        };
+        assert_distance(&triangle, [0.5, 0.5, 1.0], 1.0);    // Over the face
+        assert_distance(&triangle, [0.5, 0.5, -1.0], 1.0);   // Same on the other side
+        assert_distance(&triangle, [-1.0, -1.0, 0.0], 2.0_f32.sqrt());    // Off a corner
+        assert_distance(&triangle, [1.0, -2.0, 0.0], 2.0);   // Off an edge
+    }
+
+    #[test]
+    fn hexagonal_prism_distances() {
+        let prism = HexagonalPrism { centre: Point3::origin(), radius: 1.0, height: 2.0, material: Material::default() };
+        assert_distance(&prism, [0.0, 0.0, 2.0], 1.0);   // Out from the middle of a side
+        assert_distance(&prism, [0.0, 3.0, 0.0], 2.0);
+        assert_distance(&prism, [0.0, 0.0, 0.0], -1.0);
+    }
+}
@@ -1,67 +1,50 @@
-use na::{Point3, Point2, Vector3, Vector2, Perspective3};
+use na::{Point3, Vector3};
 
 use crate::scene::Scene;
-use crate::lighting::Color;
 use crate::objects::Object;
 
 pub const RAY_MAX_TRAVEL_DISTANCE: f32 = 1000.0; // Distance before ray stops marching
 pub const RAY_HIT_THRESHOLD: f32 = 0.001; // Minimum distance from object before it is considered hit.
-pub const RAY_REFLECT_LIMIT: usize = 2;      // Number of times ray can be reflected
+pub const RAY_REFLECT_LIMIT: usize = 2;      // Default number of times ray can be reflected
+pub const RAY_THROUGHPUT_CUTOFF: f32 = 0.01;    // Default for the least a reflection can add to a pixel and still be traced
 
 pub struct Ray {
     pub origin: Point3<f32>,
     pub direction: Vector3<f32>,
     position: Point3<f32>,
-    pub color: Color,
 }
 
 impl Ray {
     pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
         Ray {
-            origin: origin.clone(),
+            origin,
             direction,
             position: origin,
-            color: Color::white(),
         }
     }
 
-    pub fn new_with_color(origin: Point3<f32>, direction: Vector3<f32>, color: Color) -> Ray {
-        Ray {
-            origin: origin.clone(),
-            direction,
-            position: origin,
-            color: Color::white(),
-        }
-    }
+    // x and y are in pixels, and can be fractional for sampling within a pixel
+    pub fn create_prime(x: f32, y: f32, scene: &Scene) -> Ray {
+        // Normalise to -1 -> 1 across the screen, with y going upwards. ndc -> normalised device coordinates
+        let ndc_x = (x / scene.width as f32) * 2.0 - 1.0;
+        let ndc_y = 1.0 - (y / scene.height as f32) * 2.0;
 
-    pub fn create_prime(x: u32, y: u32, scene: &Scene) -> Ray {
-        let screen_point = Point2::new(x as f32, y as f32);
-
-        // From https://www.nalgebra.org/cg_recipes/#screen-space-to-view-space
-        // Normalize and make far and near points (in front and behind camera). ndc -> normalised device constant
-        let ndc_point = Point2::new((screen_point.x as f32 / scene.width as f32) * 2.0 - 1.0, 1.0 - (screen_point.y / scene.height as f32) * 2.0);
-        let near_ndc_point = Point3::new(ndc_point.x, ndc_point.y, -1.0);
-        let far_ndc_point  = Point3::new(ndc_point.x, ndc_point.y, 1.0);
-
-        // Unproject them to view-space.
-        let near_view_point = scene.perspective.unproject_point(&near_ndc_point);
-        let far_view_point  = scene.perspective.unproject_point(&far_ndc_point);
-
-        // Compute the view-space line parameters.
-        let line_direction = (far_view_point - near_view_point).normalize();
+        let (view_origin, view_direction) = scene.camera.view_space_ray(ndc_x, ndc_y, scene.width as f32/scene.height as f32);
 
+        // Then move into world space from wherever the camera is
+        let view_to_world = scene.camera.view_to_world();
         Ray::new(
-            near_view_point,
-            line_direction,
+            view_to_world * view_origin,
+            view_to_world * view_direction,
         )
     }
     
-    fn get_closest_object_estimate(&self, objects: &Vec<Box<dyn Object>>, ignore: &[usize]) -> (Option<f32>, Option<usize>) {
+    fn get_closest_object_estimate(&self, objects: &[Box<dyn Object>], ignore: &[usize]) -> (Option<f32>, Option<usize>) {
         let mut closest = (None, None);
 
         for (i, object) in objects.iter().enumerate().filter(|(i, _)| !ignore.contains(i)) {
             let distance_estimate = object.distance_estimate(&self.position);
-            if closest.0 == None || closest.0 > Some(distance_estimate) {
+            if closest.0.is_none() || closest.0 > Some(distance_estimate) {
                 closest = (Some(distance_estimate), Some(i));
             }
         }
@@ -69,8 +52,8 @@ impl Ray {
     }
 
     // Returns index of object hit first if it did hit, and the position of hit
-    // ignore is used when calculating shadows, telling it to ignore the parent object
-    pub fn march_until_hit(&mut self, objects: &Vec<Box<dyn Object>>, ignore: &[usize]) -> (Option<HitData>, f32) {    // returns (Some(object index, point of hit), distance traveled)
+    // ignore is used when calculating shadows, telling it to skip objects that don't cast them
+    pub fn march_until_hit(&mut self, objects: &[Box<dyn Object>], ignore: &[usize]) -> (Option<HitData>, f32) {    // returns (Some(object index, point of hit), distance traveled)
         // Move forwards at least once, so that if radiating from the surface of an object it doesn't just sit there
         self.position = self.origin + self.direction * RAY_HIT_THRESHOLD;
         let mut distance_traveled = RAY_HIT_THRESHOLD;
@@ -78,14 +61,7 @@ impl Ray {
         loop {
             if let (Some(smallest_distance_estimate), Some(index)) = self.get_closest_object_estimate(objects, ignore) {
                 if smallest_distance_estimate < RAY_HIT_THRESHOLD {
-                    return (Some(
-                        HitData::new(
-                            self.position,
-                            index,
-                            objects[index].get_type_name()
-                        )),
-                        distance_traveled
-                    )
+                    return (Some(HitData::new(self.position, index)), distance_traveled)
                 } else if distance_traveled > RAY_MAX_TRAVEL_DISTANCE {
                     return (None, distance_traveled)     // Didn't hit any
                 }
@@ -99,26 +75,108 @@ impl Ray {
         }
     }
 
+    // Shadow ray towards a light max_distance away. Returns how much light gets through, 0 -> 1.
+    // Objects the ray passes close to partly block the light, giving soft edges to shadows. The
+    // smaller hardness is, the wider the penumbra. From https://iquilezles.org/articles/rmshadows/
+    pub fn march_soft_shadow(&mut self, objects: &[Box<dyn Object>], ignore: &[usize], max_distance: f32, hardness: f32) -> f32 {
+        self.position = self.origin + self.direction * RAY_HIT_THRESHOLD;
+        let mut distance_traveled = RAY_HIT_THRESHOLD;
+        let mut light_amount: f32 = 1.0;
+
+        while distance_traveled < max_distance.min(RAY_MAX_TRAVEL_DISTANCE) {
+            let smallest_distance_estimate = match self.get_closest_object_estimate(objects, ignore) {
+                (Some(distance), _) => distance,
+                _ => break,     // No objects to block the light
+            };
+            if smallest_distance_estimate < RAY_HIT_THRESHOLD {
+                return 0.0
+            }
+            // Closer misses further from the surface block more light
+            light_amount = light_amount.min(hardness * smallest_distance_estimate/distance_traveled);
+
+            self.position += self.direction * smallest_distance_estimate;
+            distance_traveled += smallest_distance_estimate;
+        }
+        light_amount
+    }
+
+    // Like march_until_hit, but starting inside the object objects[medium]. Its distance field is
+    // negated so that the ray stops when it gets back out to its surface, or when it hits another
+    // object inside it. origin should already be just inside the surface.
+    pub fn march_inside(&mut self, objects: &[Box<dyn Object>], medium: usize) -> (Option<HitData>, f32) {
+        self.position = self.origin;
+        let mut distance_traveled = 0.0;
+
+        loop {
+            let mut closest = (-objects[medium].distance_estimate(&self.position), medium);
+            for (i, object) in objects.iter().enumerate().filter(|(i, _)| *i != medium) {
+                let distance_estimate = object.distance_estimate(&self.position);
+                if distance_estimate < closest.0 {
+                    closest = (distance_estimate, i);
+                }
+            }
+
+            let (smallest_distance_estimate, index) = closest;
+            if smallest_distance_estimate < RAY_HIT_THRESHOLD {
+                return (Some(HitData::new(self.position, index)), distance_traveled)
+            } else if distance_traveled > RAY_MAX_TRAVEL_DISTANCE {
+                return (None, distance_traveled)
+            }
+
+            self.position += self.direction * smallest_distance_estimate;
+            distance_traveled += smallest_distance_estimate;
+        }
+    }
+
     // Change direction of ray based on a surface normal given
     pub fn reflect(&mut self, surface_normal: Vector3<f32>) {
         // d_n = d - 2n(d . n)
         self.direction -= 2.0 * surface_normal * self.direction.dot(&surface_normal);
     }
+
+    // Bend the ray as it passes into a new material (Snell's law). surface_normal faces the side
+    // the ray is coming from, and eta is the index of refraction it is leaving over the one it is
+    // entering. Returns false, leaving the ray alone, if it is totally internally reflected instead.
+    pub fn refract(&mut self, surface_normal: Vector3<f32>, eta: f32) -> bool {
+        let cos_incident = -self.direction.dot(&surface_normal);
+        let k = 1.0 - eta * eta * (1.0 - cos_incident * cos_incident);    // cos^2 of the refracted angle
+        if k < 0.0 {
+            return false
+        }
+        self.direction = (eta * self.direction + (eta * cos_incident - k.sqrt()) * surface_normal).normalize();
+        true
+    }
+}
+
+// Fraction of light reflected rather than refracted at a boundary between indices of refraction n1
+// (the side the light comes from) and n2, using Schlick's approximation. cos_incident is the cosine
+// of the angle between the ray and the normal.
+pub fn schlick_reflectance(cos_incident: f32, n1: f32, n2: f32) -> f32 {
+    let mut cos_theta = cos_incident;
+    if n1 > n2 {
+        // Going into a less dense material the refracted angle is the larger one, and past the
+        // critical angle everything is reflected
+        let sin2_transmitted = (n1/n2).powi(2) * (1.0 - cos_incident * cos_incident);
+        if sin2_transmitted > 1.0 {
+            return 1.0
+        }
+        cos_theta = (1.0 - sin2_transmitted).sqrt();
+    }
+    let r0 = ((n1 - n2)/(n1 + n2)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
 }
 
 #[derive(Debug)]
 pub struct HitData {
     pub point_of_contact: Point3<f32>,
     pub object_index: usize,
-    pub object_type_name: &'static str,
 }
 
 impl HitData {
-    pub fn new(point_of_contact: Point3<f32>, object_index: usize, object_type_name: &'static str) -> Self {
+    pub fn new(point_of_contact: Point3<f32>, object_index: usize) -> Self {
         Self {
             point_of_contact,
             object_index,
-            object_type_name,
         }
     }
 }
\ No newline at end of file
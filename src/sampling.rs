@@ -0,0 +1,235 @@
+use na::{Point2, Point3, Vector3};
+use serde::Deserialize;
+
+use std::fmt;
+use std::str::FromStr;
+
+// Small pseudo random number generator (xorshift32). Seeded from where it is used rather than
+// shared, so renders come out the same no matter how the work is split between threads.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    pub fn new(seed: u32) -> Rng {
+        Rng {
+            state: hash(seed) | 1,     // xorshift gets stuck on 0
+        }
+    }
+
+    // Seeded from a position, so the same point always gets the same numbers
+    pub fn from_point(point: &Point3<f32>, salt: u32) -> Rng {
+        let seed = point.coords.iter()
+            .fold(hash(salt), |seed, c| hash(seed ^ c.to_bits()));
+        Rng::new(seed)
+    }
+
+    // Seeded from a pixel, for choosing where samples go inside it
+    pub fn from_pixel(x: u32, y: u32) -> Rng {
+        Rng::new(hash(x) ^ y.wrapping_mul(0x9e37_79b9))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    // 0 -> 1, not including 1
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32/(1u32 << 24) as f32
+    }
+
+    // Uniformly distributed inside a sphere of radius 1
+    pub fn in_unit_sphere(&mut self) -> Vector3<f32> {
+        loop {
+            let v = Vector3::new(self.next_f32(), self.next_f32(), self.next_f32()) * 2.0 - Vector3::repeat(1.0);
+            if v.norm_squared() <= 1.0 {
+                return v
+            }
+        }
+    }
+
+    // Direction on the side of the surface normal faces, more likely the closer it is to normal
+    // (cosine weighted). normal is unit length.
+    pub fn cosine_hemisphere(&mut self, normal: &Vector3<f32>) -> Vector3<f32> {
+        loop {
+            let direction = normal + self.in_unit_sphere().normalize();
+            if direction.norm_squared() > 1.0e-6 {     // Also skips NaN from a zero length sample
+                return direction.normalize()
+            }
+        }
+    }
+}
+
+// Mixes up the bits of x (lowbias32 from https://nullprogram.com/blog/2018/07/31/)
+pub fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+// Where the samples for one pixel go
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum SamplePattern {
+    // Evenly spaced rows and columns
+    Grid,
+    // Grid turned by about 27 degrees, so that near horizontal and vertical edges are crossed by
+    // more different rows and columns
+    RotatedGrid,
+    // One random point in each cell of the grid
+    Jittered,
+    // Halton sequence (bases 2 and 3), evenly spread for any number of samples. Shifted by a random
+    // amount in each pixel so neighbouring pixels don't line up.
+    Halton,
+}
+
+impl SamplePattern {
+    // count points between 0 and 1, rng is only used by the random patterns
+    pub fn points(&self, count: u32, rng: &mut Rng) -> Vec<Point2<f32>> {
+        // As square as possible, with the last row maybe not full
+        let columns = (count as f32).sqrt().ceil().max(1.0) as u32;
+        let rows = count.div_ceil(columns);
+        let cell = |i: u32| (i % columns, i/columns);
+
+        match self {
+            SamplePattern::Grid => (0..count)
+                .map(cell)
+                .map(|(cx, cy)| Point2::new((cx as f32 + 0.5)/columns as f32, (cy as f32 + 0.5)/rows as f32))
+                .collect(),
+            SamplePattern::RotatedGrid => {
+                let (sin, cos) = (0.5f32).atan().sin_cos();
+                (0..count)
+                    .map(cell)
+                    .map(|(cx, cy)| {
+                        // Rotate about the centre, wrapping anything that ends up outside back in
+                        let x = (cx as f32 + 0.5)/columns as f32 - 0.5;
+                        let y = (cy as f32 + 0.5)/rows as f32 - 0.5;
+                        Point2::new((x * cos - y * sin + 0.5).rem_euclid(1.0), (x * sin + y * cos + 0.5).rem_euclid(1.0))
+                    })
+                    .collect()
+            },
+            SamplePattern::Jittered => (0..count)
+                .map(cell)
+                .map(|(cx, cy)| Point2::new((cx as f32 + rng.next_f32())/columns as f32, (cy as f32 + rng.next_f32())/rows as f32))
+                .collect(),
+            SamplePattern::Halton => {
+                let offset = (rng.next_f32(), rng.next_f32());
+                (0..count)
+                    .map(|i| Point2::new((radical_inverse(i + 1, 2) + offset.0).fract(), (radical_inverse(i + 1, 3) + offset.1).fract()))
+                    .collect()
+            },
+        }
+    }
+}
+
+// i written in base, then mirrored around the decimal point. 0 -> 1.
+fn radical_inverse(mut i: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut digit_value = 1.0/base as f32;
+    while i > 0 {
+        result += (i % base) as f32 * digit_value;
+        i /= base;
+        digit_value /= base as f32;
+    }
+    result
+}
+
+// How much each sample counts towards the pixel, depending on how far it is from the pixel centre.
+// Wider filters take samples from over a bigger area, which is smoother but blurrier.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum Filter {
+    // All samples inside the pixel count the same
+    Box,
+    // Falls off in a straight line, reaching 0 one pixel away
+    Tent,
+    // Smooth falloff, slightly blurry
+    Gaussian,
+    // Mitchell-Netravali (B = C = 1/3), sharper than Gaussian, slightly negative at the edges
+    Mitchell,
+}
+
+impl Filter {
+    // Furthest a sample can be from the pixel centre, in pixels, along x or y
+    pub fn radius(&self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    // dx and dy are the sample's offset from the pixel centre in pixels
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f32) -> f32 {
+        let d = d.abs();
+        if d > self.radius() {
+            return 0.0
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - d,
+            Filter::Gaussian => {
+                // Standard deviation of half a pixel, shifted down so it reaches 0 at the radius
+                let gaussian = |x: f32| (-2.0 * x * x).exp();
+                gaussian(d) - gaussian(self.radius())
+            },
+            Filter::Mitchell => {
+                let (b, c) = (1.0/3.0, 1.0/3.0);
+                let weight = if d < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * d.powi(3) + (-18.0 + 12.0 * b + 6.0 * c) * d.powi(2) + (6.0 - 2.0 * b)
+                } else {
+                    (-b - 6.0 * c) * d.powi(3) + (6.0 * b + 30.0 * c) * d.powi(2) + (-12.0 * b - 48.0 * c) * d + (8.0 * b + 24.0 * c)
+                };
+                weight/6.0
+            },
+        }
+    }
+}
+
+// For the command line, names are lower case with dashes
+impl FromStr for SamplePattern {
+    type Err = UnknownName;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grid" => Ok(SamplePattern::Grid),
+            "rotated-grid" => Ok(SamplePattern::RotatedGrid),
+            "jittered" => Ok(SamplePattern::Jittered),
+            "halton" => Ok(SamplePattern::Halton),
+            _ => Err(UnknownName(s.to_owned())),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = UnknownName;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            _ => Err(UnknownName(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownName(String);
+
+impl fmt::Display for UnknownName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown name `{}`", self.0)
+    }
+}
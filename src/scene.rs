@@ -1,105 +1,689 @@
-use image::{DynamicImage, GenericImage, Rgba, Pixel};
-use na::{Point3, Perspective3, Vector3, Matrix4, Isometry3};
+use image::{DynamicImage, GenericImage};
+use serde::Deserialize;
+use na::{Point3, Vector3};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
-use std::f32::consts::PI;
+use std::borrow::Cow;
+use std::path::Path;
 
-use crate::objects::{Sphere, Object};
-use crate::ray::{Ray, HitData, RAY_MAX_TRAVEL_DISTANCE, RAY_REFLECT_LIMIT, RAY_HIT_THRESHOLD};
-use crate::lighting::{Light, Color};
+use crate::camera::Camera;
+use crate::objects::Object;
+use crate::ray::{Ray, HitData, RAY_MAX_TRAVEL_DISTANCE, RAY_REFLECT_LIMIT, RAY_THROUGHPUT_CUTOFF, RAY_HIT_THRESHOLD, schlick_reflectance};
+use crate::lighting::{Light, Color, Illumination, ShadingModel, ShadowMode, AmbientOcclusion};
+use crate::sampling::{Rng, SamplePattern, Filter};
+use crate::tone_mapping::ToneMapping;
+use crate::fog::Fog;
+use crate::background::{Background, EnvironmentLighting};
+use crate::material::Material;
+use crate::scene_file::{SceneDescription, SceneFileError};
+
+pub const TILE_SIZE: u32 = 32;    // Width and height of the squares the image is rendered in
+const INTERNAL_REFLECT_LIMIT: usize = 8;    // Bounces inside a transparent object before giving up on the ray
+const SHADOW_RAY_OFFSET: f32 = RAY_HIT_THRESHOLD * 4.0;  // How far off the surface shadow rays start, so they don't hit it straight away
+
+// Rectangle of pixels rendered as one unit of work
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Tile {
+    // Pixel coordinates in row order
+    fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (self.y..self.y + self.height).flat_map(move |y| (self.x..self.x + self.width).map(move |x| (x, y)))
+    }
+}
+
+// What each pixel shows
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum RenderMode {
+    Shaded,
+    // Ambient occlusion of the first surface hit, white where fully open. For tuning the settings.
+    AmbientOcclusion,
+}
 
 pub struct Scene {
     pub width: u32,
     pub height: u32,
-    pub fov: f32,       // In DEGREES
-    pub perspective: Perspective3<f32>,
+    pub camera: Camera,
     pub objects: Vec<Box<dyn Object>>,
     pub lights: Vec<Light>,
+    pub max_reflections: usize,     // Number of times a ray can be reflected or refracted
+    pub throughput_cutoff: f32,     // Reflections adding less than this much to a pixel aren't traced
+    pub samples: u32,               // Samples per pixel, at least 1
+    pub sample_pattern: SamplePattern,
+    pub filter: Filter,             // How samples are weighted and how far from the pixel they are taken
+    pub adaptive_threshold: Option<f32>,    // If set, only pixels that differ from a neighbour by more than this get all the samples
+    pub threads: usize,             // 0 uses one thread per core
+    pub shading_model: ShadingModel,
+    pub ambient_occlusion: Option<AmbientOcclusion>,     // Darkens ambient lights in creases, None turns it off
+    pub render_mode: RenderMode,
+    pub fog: Option<Fog>,
+    pub background: Background,     // Seen by rays that don't hit anything
+    pub environment_lighting: Option<EnvironmentLighting>,  // Light coming from all around, usually from the background image
+    pub tone_mapping: ToneMapping,  // How colors brighter than white are brought into range for the image
+    pub exposure: f32,              // In stops, each one doubles the brightness before tone mapping
 }
 
 impl Scene {
-    pub fn new(width: u32, height: u32, fov: f32, objects: Vec<Box<dyn Object>>, lights: Vec<Light>) -> Scene {
+    pub fn new(width: u32, height: u32, camera: Camera, objects: Vec<Box<dyn Object>>, lights: Vec<Light>) -> Scene {
         Scene {
             width,
             height,
-            perspective: Perspective3::new(width as f32/height as f32, PI/2.0, 1.0, RAY_MAX_TRAVEL_DISTANCE),
-            fov,
+            camera,
             objects,
             lights,
+            max_reflections: RAY_REFLECT_LIMIT,
+            throughput_cutoff: RAY_THROUGHPUT_CUTOFF,
+            samples: 1,
+            sample_pattern: SamplePattern::Grid,
+            filter: Filter::Box,
+            adaptive_threshold: None,
+            threads: 0,
+            shading_model: ShadingModel::BlinnPhong,
+            ambient_occlusion: None,
+            render_mode: RenderMode::Shaded,
+            fog: None,
+            background: Background::default(),
+            environment_lighting: None,
+            tone_mapping: ToneMapping::Clamp,
+            exposure: 0.0,
         }
     }
 
-    // pub fn move_camera(&mut self, transform: Isometry3<f32>) {
-    //     // self.camera_pos += d_pos;
-    //     self.perspective = Perspective3::from_matrix_unchecked(self.perspective.as_matrix() * transform);
-    // }
+    // Read a scene description (RON) from disk
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, SceneFileError> {
+        SceneDescription::load(path)?.into_scene()
+    }
+
+    pub fn add_object<O: Object + 'static>(&mut self, object: O) {
+        self.objects.push(Box::new(object));
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    pub fn set_dimensions(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
 
     pub fn render(&self) -> DynamicImage {
         let mut image = DynamicImage::new_rgb8(self.width, self.height);
 
-        for x in 0..self.width {
-            for y in 0..self.height {
-                let mut ray = Ray::create_prime(x, y, self);
+        let pixels = match self.adaptive_threshold {
+            Some(threshold) if self.samples > 1 => self.render_adaptive(threshold),
+            _ => self.render_pixels(|x, y| self.render_pixel(x, y)),
+        };
+
+        for (i, pixel) in pixels.iter().enumerate() {
+            let (x, y) = (i as u32 % self.width, i as u32/self.width);
+            image.put_pixel(x, y, self.display_color(*pixel).get_image_rgba());
+        }
+
+        image
+    }
 
-                for i in 0..RAY_REFLECT_LIMIT+1 {
-                    let (hit_data, _) = ray.march_until_hit(&self.objects, &[]);
-                    if let Some(hit_data) = hit_data { // If it hit something, add color
-                        let object_hit = &self.objects[hit_data.object_index];
+    // Calls render for every pixel, a tile at a time on the thread pool. Results are in row order.
+    fn render_pixels<T: Send, F: Fn(u32, u32) -> T + Sync>(&self, render: F) -> Vec<T> {
+        let tiles = self.tiles();
+        let render_tile = |tile: &Tile| -> Vec<T> {
+            tile.pixels().map(|(x, y)| render(x, y)).collect()
+        };
 
-                        let mut hit_color = *(object_hit.get_color_ref());
-                        let shade = self.shade(&hit_data);
-                        hit_color *= shade;
+        // Every pixel only depends on its own coordinates, so the order tiles are finished in
+        // doesn't change the image.
+        let rendered: Vec<Vec<T>> = match self.thread_pool() {
+            Some(pool) => pool.install(|| tiles.par_iter().map(render_tile).collect()),
+            None => tiles.iter().map(render_tile).collect(),
+        };
 
-                        ray.color *= &hit_color;
+        let mut pixels: Vec<Option<T>> = (0..self.width * self.height).map(|_| None).collect();
+        for (tile, tile_pixels) in tiles.iter().zip(rendered) {
+            for ((x, y), pixel) in tile.pixels().zip(tile_pixels) {
+                pixels[(y * self.width + x) as usize] = Some(pixel);
+            }
+        }
+        pixels.into_iter().map(|pixel| pixel.expect("tiles cover every pixel")).collect()
+    }
 
-                        if self.objects[hit_data.object_index].get_reflectance() == 0.0 {
-                            break;
-                        }
-                        ray.reflect(self.objects[hit_data.object_index].get_normal(&hit_data.point_of_contact));
-                    } else {    // Reached distance limit - nothing to reflect off of
-                        break;
-                    }
-                }
+    // One sample through the middle of every pixel first, then full sampling only where a pixel
+    // looks different from one of its neighbours (more than threshold in any channel once tone
+    // mapped) or shows a different object, which is mostly along edges.
+    fn render_adaptive(&self, threshold: f32) -> Vec<Color> {
+        let first_pass = self.render_pixels(|x, y| self.sample(x as f32 + 0.5, y as f32 + 0.5));
+        let displayed: Vec<Color> = first_pass.iter().map(|(color, _)| self.display_color(*color)).collect();
+        let index = |x: u32, y: u32| (y * self.width + x) as usize;
+
+        let needs_refining = |x: u32, y: u32| {
+            let i = index(x, y);
+            let (x_range, y_range) = (x.saturating_sub(1)..=(x + 1).min(self.width - 1), y.saturating_sub(1)..=(y + 1).min(self.height - 1));
+            y_range.flat_map(|ny| x_range.clone().map(move |nx| (nx, ny)))
+                .map(|(nx, ny)| index(nx, ny))
+                .any(|j| {
+                    let difference = displayed[i] - displayed[j];
+                    first_pass[i].1 != first_pass[j].1 || difference.r.abs().max(difference.g.abs()).max(difference.b.abs()) > threshold
+                })
+        };
 
-                image.put_pixel(x, y, ray.color.get_image_rgba());
+        self.render_pixels(|x, y| {
+            if needs_refining(x, y) {
+                self.render_pixel(x, y)
+            } else {
+                first_pass[index(x, y)].0
             }
+        })
+    }
+
+    // Color written to the image for a rendered pixel
+    fn display_color(&self, color: Color) -> Color {
+        match self.render_mode {
+            RenderMode::Shaded => self.tone_mapping.apply(color, self.exposure),
+            RenderMode::AmbientOcclusion => color,  // Already 0 -> 1, and should be seen as it is
         }
+    }
 
-        image
+    // None when rendering on the calling thread only
+    fn thread_pool(&self) -> Option<ThreadPool> {
+        if self.threads == 1 {
+            return None
+        }
+        // num_threads(0) lets rayon pick one thread per core. If the pool can't be created just
+        // render on this thread instead.
+        ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()
+            .ok()
     }
 
-    // Check if a point is in shadow. Retuns 0 -> 1 of how much light
+    // Split the image into TILE_SIZE squares, clipped at the right and bottom edges
+    fn tiles(&self) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y in (0..self.height).step_by(TILE_SIZE as usize) {
+            for x in (0..self.width).step_by(TILE_SIZE as usize) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: TILE_SIZE.min(self.width - x),
+                    height: TILE_SIZE.min(self.height - y),
+                });
+            }
+        }
+        tiles
+    }
+
+    // Weighted average of the samples taken around the pixel centre, spread over the filter's width
+    fn render_pixel(&self, x: u32, y: u32) -> Color {
+        let mut rng = Rng::from_pixel(x, y);
+        let radius = self.filter.radius();
+        let centre = (x as f32 + 0.5, y as f32 + 0.5);
+
+        let mut color = Color::black();
+        let mut total_weight = 0.0;
+        let mut total_abs_weight = 0.0;
+        let mut unweighted = Color::black();
+
+        let points = self.sample_pattern.points(self.samples, &mut rng);
+        for point in points.iter() {
+            let (dx, dy) = ((point.x - 0.5) * 2.0 * radius, (point.y - 0.5) * 2.0 * radius);
+            let (sample, _) = self.sample(centre.0 + dx, centre.1 + dy);
+
+            let weight = self.filter.weight(dx, dy);
+            color += sample * weight;
+            total_weight += weight;
+            total_abs_weight += weight.abs();
+            unweighted += sample;
+        }
+
+        // With only a few samples spread over a wide filter the negative parts of it can nearly cancel
+        // out the rest, and dividing by what is left would blow the pixel up
+        if total_weight > 0.5 * total_abs_weight && total_weight > 0.0 {
+            color / total_weight
+        } else {
+            unweighted / points.len().max(1) as f32
+        }
+    }
+
+    // Color seen through a point on the screen in pixels, and the index of the object hit if any
+    fn sample(&self, x: f32, y: f32) -> (Color, Option<usize>) {
+        let mut ray = Ray::create_prime(x, y, self);
+        let (hit_data, distance) = ray.march_until_hit(&self.objects, &[]);
+        let object_index = hit_data.as_ref().map(|hit_data| hit_data.object_index);
+
+        let color = match self.render_mode {
+            RenderMode::Shaded => self.radiance(ray, hit_data, distance, self.max_reflections, 1.0),
+            RenderMode::AmbientOcclusion => self.occlusion(hit_data),
+        };
+        (color, object_index)
+    }
+
+    // Light coming back along the ray. depth is how many more times it can bounce off or pass
+    // through surfaces, and throughput is how much it adds to the pixel, 0 -> 1. Rays that would
+    // add less than throughput_cutoff aren't worth following.
+    fn trace(&self, mut ray: Ray, depth: usize, throughput: f32) -> Color {
+        if throughput < self.throughput_cutoff {
+            return Color::black()
+        }
+        let (hit_data, distance) = ray.march_until_hit(&self.objects, &[]);
+        self.radiance(ray, hit_data, distance, depth, throughput)
+    }
+
+    // Light coming back along a ray that has already been marched distance, see trace
+    fn radiance(&self, ray: Ray, hit_data: Option<HitData>, distance: f32, depth: usize, throughput: f32) -> Color {
+        let (origin, direction) = (ray.origin, ray.direction);
+        // Misses go through as much fog as the furthest a ray can travel, however far the march
+        // actually got (it stops straight away when there is nothing to hit)
+        let (color, fog_distance) = match hit_data {
+            Some(hit_data) => (self.surface_radiance(ray, hit_data, depth, throughput), distance),
+            None => (self.background.color(&direction), RAY_MAX_TRAVEL_DISTANCE),
+        };
+
+        match &self.fog {
+            Some(fog) => fog.apply(color, &origin, &direction, fog_distance),
+            None => color,
+        }
+    }
+
+    // Light leaving the surface a ray hit, back along the ray
+    fn surface_radiance(&self, mut ray: Ray, hit_data: HitData, depth: usize, throughput: f32) -> Color {
+        let object_hit = &self.objects[hit_data.object_index];
+        let material = object_hit.get_material(&hit_data.point_of_contact);
+
+        let mut color = self.shade(&hit_data, &ray.direction, &material) + material.emission;
+        let normal = object_hit.get_normal(&hit_data.point_of_contact);
+
+        // Whatever isn't reflected is split between the surface itself and light coming through it.
+        // Once out of bounces the background stands in for whatever would have been traced, so
+        // mirrors and glass don't suddenly turn into plain diffuse surfaces.
+        if material.transparency > 0.0 {
+            let transmitted = if depth == 0 {
+                self.background.color(&ray.direction)
+            } else {
+                let transmitted_throughput = throughput * (1.0 - material.reflectivity) * material.transparency;
+                self.transmit(&ray, &hit_data, &normal, &material, depth, transmitted_throughput)
+            };
+            color = color * (1.0 - material.transparency) + transmitted * material.transparency;
+        }
+        if material.reflectivity > 0.0 {
+            ray.origin = hit_data.point_of_contact;
+            ray.reflect(normal);
+            let reflected = if depth == 0 {
+                self.background.color(&ray.direction)
+            } else {
+                self.trace(ray, depth - 1, throughput * material.reflectivity)
+            };
+            color = color * (1.0 - material.reflectivity) + reflected * material.reflectivity;
+        }
+        color
+    }
+
+    // Light coming from a transparent surface that a ray hit, split between what is reflected off
+    // it and what comes through it by the Fresnel term. depth is how many more bounces there can be.
+    fn transmit(&self, ray: &Ray, hit_data: &HitData, normal: &Vector3<f32>, material: &Material, depth: usize, throughput: f32) -> Color {
+        let point = hit_data.point_of_contact;
+        let reflectance = schlick_reflectance(-ray.direction.dot(normal), 1.0, material.ior);
+
+        let mut reflected = Ray::new(point, ray.direction);
+        reflected.reflect(*normal);
+        let reflected_color = self.trace(reflected, depth - 1, throughput * reflectance);
+
+        // Start just inside the surface so the inside march doesn't stop straight away
+        let mut refracted = Ray::new(point - normal * RAY_HIT_THRESHOLD * 2.0, ray.direction);
+        let refracted_color = if refracted.refract(*normal, 1.0/material.ior) {
+            self.trace_inside(refracted, hit_data.object_index, material, depth, throughput * (1.0 - reflectance))
+        } else {
+            Color::black()
+        };
+
+        reflected_color * reflectance + refracted_color * (1.0 - reflectance)
+    }
+
+    // Follow a ray through the inside of a transparent object until it refracts back out, or hits
+    // something inside it. The light is tinted by the object's albedo on the way through.
+    fn trace_inside(&self, mut ray: Ray, medium: usize, material: &Material, depth: usize, throughput: f32) -> Color {
+        for _ in 0..INTERNAL_REFLECT_LIMIT {
+            let hit_data = match ray.march_inside(&self.objects, medium) {
+                (Some(hit_data), _) => hit_data,
+                (None, _) => return Color::black(),     // Never got out
+            };
+            let point = hit_data.point_of_contact;
+
+            if hit_data.object_index != medium {
+                // Something submerged in it, only lit directly
+                let submerged = self.objects[hit_data.object_index].get_material(&point);
+                return (self.shade(&hit_data, &ray.direction, &submerged) + submerged.emission) * &material.albedo;
+            }
+
+            let normal = self.objects[medium].get_normal(&point);  // Facing out of the object
+            let mut exit = Ray::new(point + normal * RAY_HIT_THRESHOLD * 2.0, ray.direction);
+            if exit.refract(-normal, material.ior) {
+                return self.trace(exit, depth - 1, throughput) * &material.albedo;
+            }
+
+            // Total internal reflection, so bounce back inside
+            ray = Ray::new(point - normal * RAY_HIT_THRESHOLD * 2.0, ray.direction);
+            ray.reflect(normal);
+        }
+        Color::black()
+    }
+
+    // Ambient occlusion at the point a ray hit, for RenderMode::AmbientOcclusion
+    fn occlusion(&self, hit_data: Option<HitData>) -> Color {
+        match hit_data {
+            Some(hit_data) => {
+                let normal = self.objects[hit_data.object_index].get_normal(&hit_data.point_of_contact);
+                Color::grey_from_float(self.ambient_visibility(&hit_data.point_of_contact, &normal))
+            },
+            None => Color::white(),
+        }
+    }
+
+    // Light reflected towards the viewer from the lights, diffuse plus specular highlights.
+    // view_direction is the direction of the ray that hit the surface.
     #[inline]
-    fn shade(&self, hit_data: &HitData) -> f32 {
-        let mut light_amount = 0.0;     // Assume complete darkness unless there are lights
+    fn shade(&self, hit_data: &HitData, view_direction: &Vector3<f32>, material: &Material) -> Color {
+        let mut color = Color::black();     // Assume complete darkness unless there are lights, which add together
 
         // Get the surface normal at the point of hit
         let surface_normal = self.objects[hit_data.object_index].get_normal(&hit_data.point_of_contact);
-        
-        let light_dot1 = (self.lights[0].pos - hit_data.point_of_contact).dot(&surface_normal);
+        let to_eye = -view_direction;
+
+        // Shadow rays ignore objects that don't cast shadows. They start just off the surface rather
+        // than skipping the object that was hit, so concave and composite objects can still shadow
+        // themselves.
+        let shadow_ignore: Vec<usize> = self.objects.iter().enumerate()
+            .filter(|(_, object)| !object.get_material_ref().casts_shadows)
+            .map(|(i, _)| i)
+            .collect();
+        let shadow_origin = hit_data.point_of_contact + surface_normal * SHADOW_RAY_OFFSET;
 
-        for light in self.lights.iter() {
-            // Get a normal vector going from the surface to the light
-            let light_norm = (light.pos - hit_data.point_of_contact).normalize();
+        // Only worked out if there is ambient light for it to darken
+        let mut ambient_visibility = None;
+
+        for (light_index, light) in self.lights.iter().enumerate() {
+            // Ambient light isn't directional, so just lights up the diffuse color, less so in creases
+            let ambient = light.ambient_radiance(&surface_normal);
+            if !ambient.is_black() {
+                let visibility = *ambient_visibility.get_or_insert_with(|| self.ambient_visibility(&hit_data.point_of_contact, &surface_normal));
+                color += material.albedo * &ambient * visibility;
+            }
+
+            let illumination = match light.illuminate(&hit_data.point_of_contact) {
+                Some(illumination) => illumination,
+                None => continue,
+            };
             // Amount of light illuminating surface
-            let light_dot = light_norm.dot(&surface_normal);
+            let light_dot = illumination.direction.dot(&surface_normal);
             
             if light_dot > 0.0 {   // If it is less than 0, then light is away from surface, so not illuminating
-                // new ray emitted from boundary position towards light, if it hits something then it is in shadow.
-                let mut shadow_ray = Ray::new(hit_data.point_of_contact, light_norm);
-                // March, ignoring the parent object
-                let (hit_data, distance_traveled) = shadow_ray.march_until_hit(&self.objects, &[hit_data.object_index]);
-
-                // If it didn't hit anything, and didn't hit plane, it is light
-                match hit_data {
-                    Some(hit_data) => {
-                        if hit_data.object_type_name == "HorizontalPlane" {
-                            light_amount += light_dot * light.luminance;
-                        }
-                    },
-                    None => light_amount += light_dot * light.luminance,
+                let visibility = if material.receives_shadows {
+                    self.light_visibility(light, light_index, &illumination, &shadow_origin, &shadow_ignore)
+                } else {
+                    Color::white()
+                };
+                if !visibility.is_black() {
+                    let diffuse = material.albedo * light_dot;
+                    let specular = self.shading_model.specular(&surface_normal, &illumination.direction, &to_eye, material) * light_dot;
+                    color += (diffuse + specular) * &illumination.radiance * &visibility;
                 }
             }
         }
-        light_amount/self.lights.len() as f32
-    }   
-}
\ No newline at end of file
+
+        if let Some(environment) = &self.environment_lighting {
+            color += self.environment_light(environment, &shadow_origin, &surface_normal, &to_eye, material, &shadow_ignore);
+        }
+        color
+    }
+
+    // Diffuse and glossy light from the environment. point should be just off the surface (see
+    // SHADOW_RAY_OFFSET), vectors point away from the surface and are unit length.
+    fn environment_light(&self, environment: &EnvironmentLighting, point: &Point3<f32>, normal: &Vector3<f32>, to_eye: &Vector3<f32>, material: &Material, ignore: &[usize]) -> Color {
+        // The diffuse light comes from the smooth pre-blurred map, and is scaled by how much of it
+        // gets past other objects. Brighter parts of the environment count for more, so that the
+        // sun in a map casts a shadow in the right direction.
+        let mut visibility = Color::white();
+        if material.receives_shadows && environment.shadow_samples > 0 {
+            // Salted differently from every light, so it doesn't repeat their area light samples
+            let mut rng = Rng::from_point(point, self.lights.len() as u32);
+            let (mut total, mut unblocked) = (0.0, Color::black());
+            for _ in 0..environment.shadow_samples {
+                let direction = rng.cosine_hemisphere(normal);
+                let weight = environment.radiance(&direction, 0.5).luminance();
+                total += weight;
+                unblocked += self.transmittance(point, &direction, f32::INFINITY, ignore) * weight;
+            }
+            if total > 0.0 {
+                visibility = unblocked/total;
+            }
+        }
+        let mut color = material.albedo * &environment.irradiance(normal) * &visibility;
+
+        // Glossy reflection of the environment, unless something is in the way. Nearby objects
+        // only show up in reflections through the material's reflectivity. Reflective and
+        // transparent materials already trace rays that see the environment, so adding it here
+        // too would count it twice.
+        if !material.specular.is_black() && material.reflectivity == 0.0 && material.transparency == 0.0 {
+            let reflected = 2.0 * normal * normal.dot(to_eye) - to_eye;
+            let transmitted = if material.receives_shadows {
+                self.transmittance(point, &reflected, f32::INFINITY, ignore)
+            } else {
+                Color::white()
+            };
+            if !transmitted.is_black() {
+                // Schlick fresnel, surfaces seen edge on reflect more
+                let fresnel_weight = (1.0 - normal.dot(to_eye).max(0.0)).powi(5);
+                let fresnel = material.specular + (Color::white() - material.specular) * fresnel_weight;
+                color += fresnel * &environment.radiance(&reflected, material.roughness()) * &transmitted;
+            }
+        }
+        color
+    }
+
+    // How much ambient light reaches point, 0 -> 1. Always 1 with ambient occlusion turned off,
+    // except when rendering it on its own.
+    fn ambient_visibility(&self, point: &Point3<f32>, normal: &Vector3<f32>) -> f32 {
+        let settings = match (self.ambient_occlusion, self.render_mode) {
+            (Some(settings), _) => settings,
+            (None, RenderMode::AmbientOcclusion) => AmbientOcclusion::default(),
+            (None, RenderMode::Shaded) => return 1.0,
+        };
+        settings.visibility(point, normal, |p| self.distance_estimate(p))
+    }
+
+    // Distance from point to the closest object
+    pub fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        self.objects.iter()
+            .map(|object| object.distance_estimate(point))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    // How much of a light reaches point, 0 -> 1 in each channel. Transparent objects in the way
+    // tint the light rather than blocking it. point should be just off the surface, see
+    // SHADOW_RAY_OFFSET.
+    fn light_visibility(&self, light: &Light, light_index: usize, illumination: &Illumination, point: &Point3<f32>, ignore: &[usize]) -> Color {
+        match light.shadows {
+            ShadowMode::Hard => self.transmittance(point, &illumination.direction, illumination.distance, ignore),
+            ShadowMode::Soft { hardness } => {
+                // The penumbra only comes from opaque objects, transparent ones are taken care of
+                // by the transmittance
+                let opaque_ignore: Vec<usize> = ignore.iter().cloned()
+                    .chain(self.objects.iter().enumerate()
+                        .filter(|(_, object)| object.get_material_ref().transparency > 0.0)
+                        .map(|(i, _)| i))
+                    .collect();
+                let mut shadow_ray = Ray::new(*point, illumination.direction);
+                let penumbra = shadow_ray.march_soft_shadow(&self.objects, &opaque_ignore, illumination.distance, hardness);
+                if penumbra > 0.0 {
+                    self.transmittance(point, &illumination.direction, illumination.distance, ignore) * penumbra
+                } else {
+                    Color::black()
+                }
+            },
+            ShadowMode::Area { radius, samples } => {
+                // Same random numbers every time this point is shaded, so renders are repeatable
+                let mut rng = Rng::from_point(point, light_index as u32);
+                let mut unblocked = Color::black();
+                for _ in 0..samples {
+                    let (direction, distance) = light.sample_area(point, radius, &mut rng);
+                    unblocked += self.transmittance(point, &direction, distance, ignore);
+                }
+                unblocked/samples.max(1) as f32
+            },
+        }
+    }
+
+    // new ray emitted from boundary position towards light, returning how much of the light gets
+    // through before distance, 0 -> 1 in each channel. Opaque objects block it completely. Each
+    // transparent object it passes through (including one point is inside of) tints it by the
+    // object's albedo and transparency, and is then ignored so the ray can carry on through it.
+    fn transmittance(&self, point: &Point3<f32>, direction: &Vector3<f32>, distance: f32, ignore: &[usize]) -> Color {
+        let mut ignore = Cow::Borrowed(ignore);
+        let mut transmitted = Color::white();
+        let (mut origin, mut remaining) = (*point, distance);
+
+        loop {
+            let mut shadow_ray = Ray::new(origin, *direction);
+            match shadow_ray.march_until_hit(&self.objects, &ignore) {
+                (Some(hit_data), distance_traveled) if distance_traveled < remaining => {
+                    let material = self.objects[hit_data.object_index].get_material(&hit_data.point_of_contact);
+                    if material.transparency <= 0.0 {
+                        return Color::black()
+                    }
+                    transmitted *= &(material.albedo * material.transparency);
+                    if transmitted.is_black() {
+                        return transmitted
+                    }
+                    ignore.to_mut().push(hit_data.object_index);
+                    origin = hit_data.point_of_contact;
+                    remaining -= distance_traveled;
+                },
+                _ => return transmitted,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::EnvironmentMap;
+
+    // Small enough to render quickly, and not a whole number of tiles across or down
+    const TEST_SCENE: &str = "Scene(
+        render: (width: 70, height: 45, samples: 4, sample_pattern: Jittered, max_reflections: 2),
+        camera: (position: (0.0, 1.0, 3.0), look_at: (0.0, 0.0, -5.0), fov: 60.0),
+        objects: [
+            Sphere(centre: (0.0, 0.0, -5.0), radius: 1.0, material: (reflectivity: 0.5)),
+            HorizontalPlane(y: -1.0, material: (albedo: (r: 0.2, g: 0.8, b: 0.2))),
+        ],
+        lights: [
+            Point(pos: (-4.0, 5.0, -3.0), intensity: 1.0, shadows: Area(radius: 0.5, samples: 4)),
+        ],
+    )";
+
+    fn test_scene(threads: usize) -> Scene {
+        let mut scene = SceneDescription::parse(TEST_SCENE).unwrap().into_scene().unwrap();
+        scene.threads = threads;
+        scene
+    }
+
+    #[test]
+    fn transparent_objects_tint_shadows() {
+        let scene = SceneDescription::parse("Scene(
+            objects: [
+                HorizontalPlane(y: 0.0, material: (albedo: (r: 1.0, g: 0.5, b: 0.5), transparency: 0.5, ior: 1.33)),
+                Sphere(centre: (0.0, -2.0, 0.0), radius: 0.5, material: (transparency: 1.0, ior: 1.5)),
+                Sphere(centre: (5.0, -2.0, 0.0), radius: 0.5),
+            ],
+        )").unwrap().into_scene().unwrap();
+        let up = Vector3::y();
+
+        // Starting under the water, through the glass sphere and out of the water
+        let transmitted = scene.transmittance(&Point3::new(0.0, -4.0, 0.0), &up, f32::INFINITY, &[]);
+        assert!((transmitted.r - 0.5).abs() < 1.0e-6 && (transmitted.g - 0.25).abs() < 1.0e-6, "{:?}", transmitted);
+
+        // Opaque objects still block the light
+        assert!(scene.transmittance(&Point3::new(5.0, -4.0, 0.0), &up, f32::INFINITY, &[]).is_black());
+        // Unless they are past the light
+        assert!(!scene.transmittance(&Point3::new(5.0, -4.0, 0.0), &up, 1.0, &[]).is_black());
+    }
+
+    #[test]
+    fn mirror_out_of_bounces_reflects_background() {
+        let scene = SceneDescription::parse("Scene(
+            render: (width: 9, height: 9, max_reflections: 0),
+            objects: [Sphere(centre: (0.0, 0.0, -5.0), radius: 1.0, material: (albedo: (r: 1.0, g: 0.0, b: 0.0), reflectivity: 1.0))],
+            lights: [Ambient(intensity: 1.0)],
+            background: Solid(color: (r: 0.0, g: 0.0, b: 1.0)),
+        )").unwrap().into_scene().unwrap();
+
+        let (color, object_index) = scene.sample(4.5, 4.5);
+        assert_eq!(object_index, Some(0));
+        assert_eq!(color, Color::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn reflective_materials_see_the_environment_once() {
+        let scene = Scene::new(1, 1, Camera::default(), vec![], vec![]);
+        let map = EnvironmentMap::new(2, 1, vec![Color::white(), Color::white()]);
+        let environment = EnvironmentLighting::new(&map, 1.0, 0);
+        let (point, normal) = (Point3::origin(), Vector3::y());
+
+        let glossy = Material { albedo: Color::black(), specular: Color::white(), ..Material::default() };
+        assert!(!scene.environment_light(&environment, &point, &normal, &normal, &glossy, &[]).is_black());
+
+        // The reflected ray traced for the reflectivity already picks up the environment
+        let mirror = Material { reflectivity: 0.5, ..glossy };
+        assert!(scene.environment_light(&environment, &point, &normal, &normal, &mirror, &[]).is_black());
+    }
+
+    #[test]
+    fn composite_objects_shadow_themselves() {
+        let lights = "lights: [Directional(direction: (0.0, -1.0, 0.0), intensity: 1.0)]";
+        let upper = "Sphere(centre: (0.0, 2.0, 0.0), radius: 0.5)";
+        let lower = "Sphere(centre: (0.0, 0.0, 0.0), radius: 0.5)";
+        let union = SceneDescription::parse(&format!("Scene(objects: [Union(a: {}, b: {})], {})", upper, lower, lights))
+            .unwrap().into_scene().unwrap();
+        let separate = SceneDescription::parse(&format!("Scene(objects: [{}, {}], {})", upper, lower, lights))
+            .unwrap().into_scene().unwrap();
+
+        let shade = |scene: &Scene, point: Point3<f32>, object_index: usize| {
+            let hit_data = HitData::new(point, object_index);
+            scene.shade(&hit_data, &-Vector3::y(), &Material::default())
+        };
+
+        // Top of the lower sphere is under the upper one
+        let under = Point3::new(0.0, 0.5, 0.0);
+        assert!(shade(&separate, under, 1).is_black());
+        assert!(shade(&union, under, 0).is_black());
+
+        // Top of the upper sphere isn't shadowed by its own surface
+        let top = Point3::new(0.0, 2.5, 0.0);
+        assert!(!shade(&separate, top, 0).is_black());
+        assert_eq!(shade(&union, top, 0), shade(&separate, top, 0));
+    }
+
+    #[test]
+    fn misses_are_fogged_without_objects() {
+        let scene = SceneDescription::parse("Scene(
+            render: (width: 9, height: 9),
+            fog: Exponential(color: (r: 0.0, g: 0.0, b: 0.0), density: 1.0),
+            background: Solid(color: (r: 1.0, g: 1.0, b: 1.0)),
+        )").unwrap().into_scene().unwrap();
+
+        let (color, object_index) = scene.sample(4.5, 4.5);
+        assert_eq!(object_index, None);
+        assert!(color.luminance() < 1.0e-6, "{:?}", color);
+    }
+
+    #[test]
+    fn parallel_render_matches_single_threaded() {
+        let single = test_scene(1).render();
+        let parallel = test_scene(0).render();
+        assert_eq!(single.to_bytes(), parallel.to_bytes());
+    }
+}
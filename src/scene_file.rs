@@ -0,0 +1,1023 @@
+use na::{Point3, Vector3};
+use ron::extensions::Extensions;
+use serde::Deserialize;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::scene::{Scene, RenderMode};
+use crate::camera::{Camera, FovAxis, Projection};
+use crate::objects::*;
+use crate::transform::Transformed;
+use crate::csg::{Union, Intersection, Difference, SmoothUnion, SmoothIntersection, SmoothDifference};
+use crate::lighting::{Light, Color, Attenuation, ShadingModel, ShadowMode, AmbientOcclusion};
+use crate::material::Material;
+use crate::tone_mapping::ToneMapping;
+use crate::fog::Fog;
+use crate::background::{Background, Sky, EnvironmentMap, EnvironmentLighting};
+use crate::sampling::{SamplePattern, Filter};
+use crate::ray::{RAY_REFLECT_LIMIT, RAY_THROUGHPUT_CUTOFF};
+
+// Scene files are written in RON, e.g.
+//
+// Scene(
+//     render: (width: 1920, height: 1080, ambient_occlusion: (steps: 5, distance: 0.5, strength: 1.0)),
+//     camera: (position: (0.0, 1.0, 5.0), look_at: (0.0, 0.0, -5.0), fov: 90.0, fov_axis: Vertical),
+//     objects: [
+//         Sphere(
+//             centre: (0.0, 1.0, -5.0),
+//             radius: 1.0,
+//             material: (albedo: (r: 0.0, g: 1.0, b: 0.0), reflectivity: 0.5),
+//         ),
+//         HorizontalPlane(y: -6.0, material: (albedo: (r: 0.0, g: 1.0, b: 0.0))),
+//     ],
+//     lights: [
+//         Point(pos: (-4.0, 5.0, -3.0), color: (r: 1.0, g: 0.9, b: 0.8), intensity: 20.0, attenuation: InverseSquare),
+//         Directional(direction: (-1.0, -2.0, -1.0), intensity: 0.5, shadows: Soft(hardness: 16.0)),
+//         Spot(pos: (0.0, 5.0, -5.0), direction: (0.0, -1.0, 0.0), angle: 30.0, softness: 0.2),
+//         Ambient(color: (r: 0.6, g: 0.7, b: 1.0), ground_color: (r: 0.3, g: 0.2, b: 0.1), intensity: 0.1),
+//     ],
+//     fog: Exponential(color: (r: 0.7, g: 0.75, b: 0.8), density: 0.02),
+//     background: Sky(sun_direction: (1.0, 0.5, -1.0)),
+// )
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "Scene", deny_unknown_fields)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub render: RenderDescription,
+    #[serde(default)]
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub objects: Vec<ObjectDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    #[serde(default)]
+    pub fog: Option<Fog>,
+    #[serde(default)]
+    pub background: Option<BackgroundDescription>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderDescription {
+    pub width: u32,
+    pub height: u32,
+    pub max_reflections: usize,
+    pub throughput_cutoff: f32,
+    pub samples: u32,
+    pub sample_pattern: SamplePattern,
+    pub filter: Filter,
+    pub adaptive_threshold: Option<f32>,
+    pub shading_model: ShadingModel,
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    pub mode: RenderMode,
+    pub tone_mapping: ToneMapping,
+    pub exposure: f32,
+}
+
+impl Default for RenderDescription {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            max_reflections: RAY_REFLECT_LIMIT,
+            throughput_cutoff: RAY_THROUGHPUT_CUTOFF,
+            samples: 1,
+            sample_pattern: SamplePattern::Grid,
+            filter: Filter::Box,
+            adaptive_threshold: None,
+            shading_model: ShadingModel::BlinnPhong,
+            ambient_occlusion: None,
+            mode: RenderMode::Shaded,
+            tone_mapping: ToneMapping::Clamp,
+            exposure: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraDescription {
+    pub position: [f32; 3],
+    pub look_at: [f32; 3],
+    pub up: [f32; 3],
+    pub fov: f32,   // In DEGREES
+    pub fov_axis: FovAxis,
+    pub projection: ProjectionDescription,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum ProjectionDescription {
+    Perspective,    // Uses the camera's fov
+    Orthographic {
+        height: f32,
+    },
+}
+
+impl Default for CameraDescription {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0, 0.0],
+            look_at: [0.0, 0.0, -1.0],
+            up: [0.0, 1.0, 0.0],
+            fov: 90.0,
+            fov_axis: FovAxis::Vertical,
+            projection: ProjectionDescription::Perspective,
+        }
+    }
+}
+
+impl CameraDescription {
+    pub fn into_camera(self) -> Camera {
+        let mut camera = Camera::new(Point3::from(self.position), Point3::from(self.look_at), Vector3::from(self.up), self.fov);
+        camera.projection = match self.projection {
+            ProjectionDescription::Perspective => Projection::Perspective {
+                fov: self.fov,
+                fov_axis: self.fov_axis,
+            },
+            ProjectionDescription::Orthographic { height } => Projection::Orthographic {
+                height,
+            },
+        };
+        camera
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum ObjectDescription {
+    Sphere {
+        centre: [f32; 3],
+        radius: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    HorizontalPlane {
+        y: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    AxisAlignedCube {
+        centre: [f32; 3],
+        size: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    Cuboid {
+        centre: [f32; 3],
+        half_extents: [f32; 3],
+        #[serde(default)]
+        material: Material,
+    },
+    RoundedCuboid {
+        centre: [f32; 3],
+        half_extents: [f32; 3],
+        radius: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    Torus {
+        centre: [f32; 3],
+        major_radius: f32,
+        minor_radius: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    Capsule {
+        a: [f32; 3],
+        b: [f32; 3],
+        radius: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    Cylinder {
+        centre: [f32; 3],
+        radius: f32,
+        height: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    InfiniteCylinder {
+        centre: [f32; 3],
+        radius: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    Cone {
+        apex: [f32; 3],
+        radius: f32,
+        height: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    Ellipsoid {
+        centre: [f32; 3],
+        radii: [f32; 3],
+        #[serde(default)]
+        material: Material,
+    },
+    Plane {
+        normal: [f32; 3],
+        distance: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    Triangle {
+        a: [f32; 3],
+        b: [f32; 3],
+        c: [f32; 3],
+        #[serde(default)]
+        material: Material,
+    },
+    HexagonalPrism {
+        centre: [f32; 3],
+        radius: f32,
+        height: f32,
+        #[serde(default)]
+        material: Material,
+    },
+    Union {
+        a: Box<ObjectDescription>,
+        b: Box<ObjectDescription>,
+    },
+    Intersection {
+        a: Box<ObjectDescription>,
+        b: Box<ObjectDescription>,
+    },
+    Difference {
+        a: Box<ObjectDescription>,
+        b: Box<ObjectDescription>,
+    },
+    SmoothUnion {
+        a: Box<ObjectDescription>,
+        b: Box<ObjectDescription>,
+        radius: f32,
+    },
+    SmoothIntersection {
+        a: Box<ObjectDescription>,
+        b: Box<ObjectDescription>,
+        radius: f32,
+    },
+    SmoothDifference {
+        a: Box<ObjectDescription>,
+        b: Box<ObjectDescription>,
+        radius: f32,
+    },
+    Transformed {
+        object: Box<ObjectDescription>,
+        #[serde(default)]
+        translation: [f32; 3],
+        #[serde(default)]
+        rotation: [f32; 3],     // Euler angles (roll, pitch, yaw) in DEGREES
+        #[serde(default = "default_scale")]
+        scale: f32,
+    },
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+impl ObjectDescription {
+    pub fn into_object(self) -> Box<dyn Object> {
+        match self {
+            ObjectDescription::Sphere { centre, radius, material } => Box::new(Sphere {
+                centre: Point3::from(centre),
+                radius,
+                material,
+            }),
+            ObjectDescription::HorizontalPlane { y, material } => Box::new(HorizontalPlane {
+                y,
+                material,
+            }),
+            ObjectDescription::AxisAlignedCube { centre, size, material } => Box::new(AxisAlignedCube {
+                centre: Point3::from(centre),
+                size,
+                material,
+            }),
+            ObjectDescription::Cuboid { centre, half_extents, material } => Box::new(Cuboid {
+                centre: Point3::from(centre),
+                half_extents: Vector3::from(half_extents),
+                material,
+            }),
+            ObjectDescription::RoundedCuboid { centre, half_extents, radius, material } => Box::new(RoundedCuboid {
+                centre: Point3::from(centre),
+                half_extents: Vector3::from(half_extents),
+                radius,
+                material,
+            }),
+            ObjectDescription::Torus { centre, major_radius, minor_radius, material } => Box::new(Torus {
+                centre: Point3::from(centre),
+                major_radius,
+                minor_radius,
+                material,
+            }),
+            ObjectDescription::Capsule { a, b, radius, material } => Box::new(Capsule {
+                a: Point3::from(a),
+                b: Point3::from(b),
+                radius,
+                material,
+            }),
+            ObjectDescription::Cylinder { centre, radius, height, material } => Box::new(Cylinder {
+                centre: Point3::from(centre),
+                radius,
+                height,
+                material,
+            }),
+            ObjectDescription::InfiniteCylinder { centre, radius, material } => Box::new(InfiniteCylinder {
+                centre: Point3::from(centre),
+                radius,
+                material,
+            }),
+            ObjectDescription::Cone { apex, radius, height, material } => Box::new(Cone {
+                apex: Point3::from(apex),
+                radius,
+                height,
+                material,
+            }),
+            ObjectDescription::Ellipsoid { centre, radii, material } => Box::new(Ellipsoid {
+                centre: Point3::from(centre),
+                radii: Vector3::from(radii),
+                material,
+            }),
+            ObjectDescription::Plane { normal, distance, material } => Box::new(Plane::new(
+                Vector3::from(normal),
+                distance,
+                material,
+            )),
+            ObjectDescription::Triangle { a, b, c, material } => Box::new(Triangle {
+                a: Point3::from(a),
+                b: Point3::from(b),
+                c: Point3::from(c),
+                material,
+            }),
+            ObjectDescription::HexagonalPrism { centre, radius, height, material } => Box::new(HexagonalPrism {
+                centre: Point3::from(centre),
+                radius,
+                height,
+                material,
+            }),
+            ObjectDescription::Union { a, b } => Box::new(Union {
+                a: a.into_object(),
+                b: b.into_object(),
+            }),
+            ObjectDescription::Intersection { a, b } => Box::new(Intersection {
+                a: a.into_object(),
+                b: b.into_object(),
+            }),
+            ObjectDescription::Difference { a, b } => Box::new(Difference {
+                a: a.into_object(),
+                b: b.into_object(),
+            }),
+            ObjectDescription::SmoothUnion { a, b, radius } => Box::new(SmoothUnion {
+                a: a.into_object(),
+                b: b.into_object(),
+                radius,
+            }),
+            ObjectDescription::SmoothIntersection { a, b, radius } => Box::new(SmoothIntersection {
+                a: a.into_object(),
+                b: b.into_object(),
+                radius,
+            }),
+            ObjectDescription::SmoothDifference { a, b, radius } => Box::new(SmoothDifference {
+                a: a.into_object(),
+                b: b.into_object(),
+                radius,
+            }),
+            ObjectDescription::Transformed { object, translation, rotation, scale } => Box::new(Transformed::from_parts(
+                object.into_object(),
+                Vector3::from(translation),
+                Vector3::from(rotation),
+                scale,
+            )),
+        }
+    }
+
+    // None for objects built out of other objects, which have their own
+    fn material(&self) -> Option<&Material> {
+        match self {
+            ObjectDescription::Sphere { material, .. } |
+            ObjectDescription::HorizontalPlane { material, .. } |
+            ObjectDescription::AxisAlignedCube { material, .. } |
+            ObjectDescription::Cuboid { material, .. } |
+            ObjectDescription::RoundedCuboid { material, .. } |
+            ObjectDescription::Torus { material, .. } |
+            ObjectDescription::Capsule { material, .. } |
+            ObjectDescription::Cylinder { material, .. } |
+            ObjectDescription::InfiniteCylinder { material, .. } |
+            ObjectDescription::Cone { material, .. } |
+            ObjectDescription::Ellipsoid { material, .. } |
+            ObjectDescription::Plane { material, .. } |
+            ObjectDescription::Triangle { material, .. } |
+            ObjectDescription::HexagonalPrism { material, .. } => Some(material),
+            _ => None,
+        }
+    }
+
+    // path is where the object is in the file, used in error messages
+    fn validate(&self, path: &str) -> Result<(), SceneFileError> {
+        let invalid = |name: &str, message: String| SceneFileError::Invalid {
+            field: format!("{}.{}", path, name),
+            message,
+        };
+        if let Some(material) = self.material() {
+            validate_material(material, &format!("{}.material", path))?;
+        }
+        let positive = |name: &str, value: f32| {
+            if value > 0.0 {
+                Ok(())
+            } else {
+                Err(invalid(name, format!("must be positive, got {}", value)))
+            }
+        };
+        let all_positive = |name: &str, values: &[f32; 3]| {
+            if values.iter().all(|v| *v > 0.0) {
+                Ok(())
+            } else {
+                Err(invalid(name, format!("must all be positive, got {:?}", values)))
+            }
+        };
+
+        match self {
+            ObjectDescription::Sphere { radius, .. } => positive("radius", *radius),
+            ObjectDescription::AxisAlignedCube { size, .. } => positive("size", *size),
+            ObjectDescription::HorizontalPlane { .. } => Ok(()),
+            ObjectDescription::Cuboid { half_extents, .. } => all_positive("half_extents", half_extents),
+            ObjectDescription::RoundedCuboid { half_extents, radius, .. } => {
+                all_positive("half_extents", half_extents)?;
+                positive("radius", *radius)?;
+                let smallest = half_extents.iter().cloned().fold(f32::INFINITY, f32::min);
+                if *radius > smallest {
+                    return Err(invalid("radius", format!("must not be bigger than the smallest half extent ({}), got {}", smallest, radius)))
+                }
+                Ok(())
+            },
+            ObjectDescription::Torus { major_radius, minor_radius, .. } => {
+                positive("major_radius", *major_radius)?;
+                positive("minor_radius", *minor_radius)
+            },
+            ObjectDescription::Capsule { radius, .. } => positive("radius", *radius),
+            ObjectDescription::InfiniteCylinder { radius, .. } => positive("radius", *radius),
+            ObjectDescription::Cylinder { radius, height, .. } |
+            ObjectDescription::Cone { radius, height, .. } |
+            ObjectDescription::HexagonalPrism { radius, height, .. } => {
+                positive("radius", *radius)?;
+                positive("height", *height)
+            },
+            ObjectDescription::Ellipsoid { radii, .. } => all_positive("radii", radii),
+            ObjectDescription::Plane { normal, .. } => {
+                if Vector3::from(*normal).norm() == 0.0 {
+                    return Err(invalid("normal", "must not be zero".to_owned()))
+                }
+                Ok(())
+            },
+            ObjectDescription::Triangle { a, b, c, .. } => {
+                let (a, b, c) = (Point3::from(*a), Point3::from(*b), Point3::from(*c));
+                if (b - a).cross(&(c - a)).norm() == 0.0 {
+                    return Err(invalid("c", "corners must not all be in a line".to_owned()))
+                }
+                Ok(())
+            },
+            ObjectDescription::Union { a, b } |
+            ObjectDescription::Intersection { a, b } |
+            ObjectDescription::Difference { a, b } => {
+                a.validate(&format!("{}.a", path))?;
+                b.validate(&format!("{}.b", path))
+            },
+            ObjectDescription::SmoothUnion { a, b, radius } |
+            ObjectDescription::SmoothIntersection { a, b, radius } |
+            ObjectDescription::SmoothDifference { a, b, radius } => {
+                positive("radius", *radius)?;
+                a.validate(&format!("{}.a", path))?;
+                b.validate(&format!("{}.b", path))
+            },
+            ObjectDescription::Transformed { object, scale, .. } => {
+                positive("scale", *scale)?;
+                object.validate(&format!("{}.object", path))
+            },
+        }
+    }
+}
+
+// Catch material values that would break the shading, path is where the material is in the file
+fn validate_material(material: &Material, path: &str) -> Result<(), SceneFileError> {
+    let invalid = |name: &str, message: String| Err(SceneFileError::Invalid {
+        field: format!("{}.{}", path, name),
+        message,
+    });
+
+    for (name, color) in [("albedo", &material.albedo), ("specular", &material.specular), ("emission", &material.emission)] {
+        if color.r < 0.0 || color.g < 0.0 || color.b < 0.0 {
+            return invalid(name, format!("must not be negative, got {:?}", color))
+        }
+    }
+    if material.shininess < 0.0 {
+        return invalid("shininess", format!("must not be negative, got {}", material.shininess))
+    }
+    if !(0.0..=1.0).contains(&material.reflectivity) {
+        return invalid("reflectivity", format!("must be between 0 and 1, got {}", material.reflectivity))
+    }
+    if !(0.0..=1.0).contains(&material.transparency) {
+        return invalid("transparency", format!("must be between 0 and 1, got {}", material.transparency))
+    }
+    if material.ior <= 0.0 {
+        return invalid("ior", format!("must be positive, got {}", material.ior))
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum LightDescription {
+    Point {
+        pos: [f32; 3],
+        #[serde(default = "default_light_color")]
+        color: Color,
+        #[serde(default = "default_intensity", alias = "luminance")]
+        intensity: f32,
+        #[serde(default = "default_attenuation")]
+        attenuation: Attenuation,
+        #[serde(default = "default_shadows")]
+        shadows: ShadowMode,
+    },
+    Directional {
+        direction: [f32; 3],
+        #[serde(default = "default_light_color")]
+        color: Color,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        #[serde(default = "default_shadows")]
+        shadows: ShadowMode,
+    },
+    Spot {
+        pos: [f32; 3],
+        direction: [f32; 3],
+        angle: f32,     // In DEGREES
+        #[serde(default)]
+        softness: f32,
+        #[serde(default = "default_light_color")]
+        color: Color,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        #[serde(default = "default_attenuation")]
+        attenuation: Attenuation,
+        #[serde(default = "default_shadows")]
+        shadows: ShadowMode,
+    },
+    Ambient {
+        #[serde(default = "default_light_color")]
+        color: Color,
+        // Color from below, same as color if not given
+        #[serde(default)]
+        ground_color: Option<Color>,
+        #[serde(default = "default_ambient_intensity")]
+        intensity: f32,
+    },
+}
+
+fn default_light_color() -> Color {
+    Color::white()
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_ambient_intensity() -> f32 {
+    0.1
+}
+
+fn default_attenuation() -> Attenuation {
+    Attenuation::None
+}
+
+fn default_shadows() -> ShadowMode {
+    ShadowMode::Hard
+}
+
+impl LightDescription {
+    pub fn into_light(self) -> Light {
+        let (mut light, shadows) = match self {
+            LightDescription::Point { pos, color, intensity, attenuation, shadows } => {
+                (Light::with_attenuation(Point3::from(pos), color, intensity, attenuation), shadows)
+            },
+            LightDescription::Directional { direction, color, intensity, shadows } => {
+                (Light::directional(Vector3::from(direction), color, intensity), shadows)
+            },
+            LightDescription::Spot { pos, direction, angle, softness, color, intensity, attenuation, shadows } => {
+                (Light::spot(Point3::from(pos), Vector3::from(direction), angle, softness, color, intensity, attenuation), shadows)
+            },
+            LightDescription::Ambient { color, ground_color, intensity } => {
+                (Light::hemisphere(color, ground_color.unwrap_or(color), intensity), ShadowMode::Hard)
+            },
+        };
+        light.shadows = shadows;
+        light
+    }
+
+    fn validate(&self, path: &str) -> Result<(), SceneFileError> {
+        let invalid = |name: &str, message: String| Err(SceneFileError::Invalid {
+            field: format!("{}.{}", path, name),
+            message,
+        });
+
+        let shadows = match self {
+            LightDescription::Point { shadows, .. } |
+            LightDescription::Directional { shadows, .. } |
+            LightDescription::Spot { shadows, .. } => *shadows,
+            LightDescription::Ambient { .. } => ShadowMode::Hard,
+        };
+        match shadows {
+            ShadowMode::Soft { hardness } if hardness <= 0.0 => {
+                return invalid("shadows.hardness", format!("must be positive, got {}", hardness))
+            },
+            ShadowMode::Area { radius, .. } if radius < 0.0 => {
+                return invalid("shadows.radius", format!("must not be negative, got {}", radius))
+            },
+            ShadowMode::Area { samples: 0, .. } => {
+                return invalid("shadows.samples", "must be at least 1".to_owned())
+            },
+            _ => (),
+        }
+
+        match self {
+            LightDescription::Directional { direction, .. } if Vector3::from(*direction).norm() == 0.0 => {
+                invalid("direction", "must not be zero".to_owned())
+            },
+            LightDescription::Spot { direction, .. } if Vector3::from(*direction).norm() == 0.0 => {
+                invalid("direction", "must not be zero".to_owned())
+            },
+            LightDescription::Spot { angle, .. } if !(*angle > 0.0 && *angle < 180.0) => {
+                invalid("angle", format!("must be between 0 and 180 degrees, got {}", angle))
+            },
+            LightDescription::Spot { softness, .. } if !(0.0..=1.0).contains(softness) => {
+                invalid("softness", format!("must be between 0 and 1, got {}", softness))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum BackgroundDescription {
+    Solid {
+        color: Color,
+    },
+    Gradient {
+        bottom: Color,
+        top: Color,
+    },
+    Sky {
+        sun_direction: [f32; 3],    // Towards the sun
+        #[serde(default = "default_turbidity")]
+        turbidity: f32,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        #[serde(default = "default_sun_intensity")]
+        sun_intensity: f32,
+        #[serde(default = "default_sun_size")]
+        sun_size: f32,
+        #[serde(default = "default_ground")]
+        ground: Color,
+    },
+    // Equirectangular panorama, a Radiance .hdr file or any normal image. Relative paths are from
+    // the scene file.
+    Image {
+        path: PathBuf,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        #[serde(default)]
+        rotation: f32,
+        // Also light the scene with the image
+        #[serde(default)]
+        lighting: Option<EnvironmentLightingDescription>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnvironmentLightingDescription {
+    pub intensity: f32,
+    pub shadow_samples: u32,
+}
+
+impl Default for EnvironmentLightingDescription {
+    fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            shadow_samples: 16,
+        }
+    }
+}
+
+fn default_turbidity() -> f32 {
+    3.0
+}
+
+fn default_sun_intensity() -> f32 {
+    20.0
+}
+
+fn default_sun_size() -> f32 {
+    0.5
+}
+
+fn default_ground() -> Color {
+    Color::grey_from_float(0.3)
+}
+
+impl BackgroundDescription {
+    // The lighting is only there for images with lighting turned on
+    pub fn into_background(self) -> Result<(Background, Option<EnvironmentLighting>), SceneFileError> {
+        Ok(match self {
+            BackgroundDescription::Solid { color } => (Background::Solid(color), None),
+            BackgroundDescription::Gradient { bottom, top } => (Background::Gradient { bottom, top }, None),
+            BackgroundDescription::Sky { sun_direction, turbidity, intensity, sun_intensity, sun_size, ground } => (Background::Sky(Sky {
+                sun_direction: Vector3::from(sun_direction).normalize(),
+                turbidity,
+                intensity,
+                sun_intensity,
+                sun_size,
+                ground,
+            }), None),
+            BackgroundDescription::Image { path, intensity, rotation, lighting } => {
+                let mut map = EnvironmentMap::load(&path).map_err(|e| SceneFileError::Load {
+                    path: path.display().to_string(),
+                    message: e.to_string(),
+                })?;
+                map.intensity = intensity;
+                map.rotation = rotation;
+                let lighting = lighting.map(|lighting| EnvironmentLighting::new(&map, lighting.intensity, lighting.shadow_samples));
+                (Background::Image(map), lighting)
+            },
+        })
+    }
+
+    fn validate(&self) -> Result<(), SceneFileError> {
+        let invalid = |field: &str, message: String| Err(SceneFileError::Invalid {
+            field: format!("background.{}", field),
+            message,
+        });
+        match self {
+            BackgroundDescription::Sky { sun_direction, turbidity, .. } => {
+                if Vector3::from(*sun_direction).norm() == 0.0 {
+                    return invalid("sun_direction", "must not be zero".to_owned())
+                }
+                if !(1.0..=20.0).contains(turbidity) {
+                    return invalid("turbidity", format!("must be between 1 and 20, got {}", turbidity))
+                }
+                Ok(())
+            },
+            BackgroundDescription::Image { lighting: Some(lighting), .. } if lighting.intensity < 0.0 => {
+                invalid("lighting.intensity", format!("must not be negative, got {}", lighting.intensity))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+impl SceneDescription {
+    pub fn parse(source: &str) -> Result<Self, SceneFileError> {
+        // Optional fields can be written without wrapping them in Some(...)
+        let description: SceneDescription = ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_str(source)?;
+        description.validate()?;
+        Ok(description)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneFileError> {
+        let source = fs::read_to_string(&path)?;
+        let mut description = Self::parse(&source)?;
+
+        // Files the scene refers to are found relative to it
+        if let (Some(BackgroundDescription::Image { path: image_path, .. }), Some(dir)) = (&mut description.background, path.as_ref().parent()) {
+            *image_path = dir.join(&*image_path);
+        }
+        Ok(description)
+    }
+
+    // Catch values that parse fine but can't be rendered
+    fn validate(&self) -> Result<(), SceneFileError> {
+        if self.render.width == 0 || self.render.height == 0 {
+            return Err(SceneFileError::Invalid {
+                field: "render".to_owned(),
+                message: format!("image dimensions must be non-zero, got {}x{}", self.render.width, self.render.height),
+            })
+        }
+        if self.render.samples == 0 {
+            return Err(SceneFileError::Invalid {
+                field: "render.samples".to_owned(),
+                message: "must be at least 1".to_owned(),
+            })
+        }
+        if let Some(threshold) = self.render.adaptive_threshold {
+            if threshold < 0.0 {
+                return Err(SceneFileError::Invalid {
+                    field: "render.adaptive_threshold".to_owned(),
+                    message: format!("must not be negative, got {}", threshold),
+                })
+            }
+        }
+        if !(0.0..=1.0).contains(&self.render.throughput_cutoff) {
+            return Err(SceneFileError::Invalid {
+                field: "render.throughput_cutoff".to_owned(),
+                message: format!("must be between 0 and 1, got {}", self.render.throughput_cutoff),
+            })
+        }
+        if let Some(ambient_occlusion) = &self.render.ambient_occlusion {
+            if ambient_occlusion.steps == 0 || ambient_occlusion.distance <= 0.0 || ambient_occlusion.strength < 0.0 {
+                return Err(SceneFileError::Invalid {
+                    field: "render.ambient_occlusion".to_owned(),
+                    message: "steps and distance must be positive, and strength must not be negative".to_owned(),
+                })
+            }
+        }
+        let view_direction = Point3::from(self.camera.look_at) - Point3::from(self.camera.position);
+        if view_direction.norm() == 0.0 {
+            return Err(SceneFileError::Invalid {
+                field: "camera.look_at".to_owned(),
+                message: "must be different from the camera position".to_owned(),
+            })
+        }
+        if view_direction.cross(&Vector3::from(self.camera.up)).norm() == 0.0 {
+            return Err(SceneFileError::Invalid {
+                field: "camera.up".to_owned(),
+                message: "must not be zero or parallel to the view direction".to_owned(),
+            })
+        }
+        if !(self.camera.fov > 0.0 && self.camera.fov < 180.0) {
+            return Err(SceneFileError::Invalid {
+                field: "camera.fov".to_owned(),
+                message: format!("must be between 0 and 180 degrees, got {}", self.camera.fov),
+            })
+        }
+        if let ProjectionDescription::Orthographic { height } = self.camera.projection {
+            if height <= 0.0 {
+                return Err(SceneFileError::Invalid {
+                    field: "camera.projection.height".to_owned(),
+                    message: format!("must be positive, got {}", height),
+                })
+            }
+        }
+
+        if let Some(background) = &self.background {
+            background.validate()?;
+        }
+        if let Some(fog) = &self.fog {
+            let invalid = |message: String| Err(SceneFileError::Invalid {
+                field: "fog".to_owned(),
+                message,
+            });
+            match *fog {
+                Fog::Linear { start, end, .. } if end <= start => return invalid(format!("end must be after start, got {} -> {}", start, end)),
+                Fog::Exponential { density, .. } | Fog::ExponentialSquared { density, .. } | Fog::Height { density, .. } if density < 0.0 => {
+                    return invalid(format!("density must not be negative, got {}", density))
+                },
+                Fog::Height { falloff, .. } if falloff < 0.0 => return invalid(format!("falloff must not be negative, got {}", falloff)),
+                _ => (),
+            }
+        }
+
+        for (i, object) in self.objects.iter().enumerate() {
+            object.validate(&format!("objects[{}]", i))?;
+        }
+        for (i, light) in self.lights.iter().enumerate() {
+            light.validate(&format!("lights[{}]", i))?;
+        }
+        Ok(())
+    }
+
+    // Fails if a file the scene refers to can't be loaded
+    pub fn into_scene(self) -> Result<Scene, SceneFileError> {
+        let mut scene = Scene::new(
+            self.render.width,
+            self.render.height,
+            self.camera.into_camera(),
+            self.objects.into_iter().map(ObjectDescription::into_object).collect(),
+            self.lights.into_iter().map(LightDescription::into_light).collect(),
+        );
+        scene.max_reflections = self.render.max_reflections;
+        scene.throughput_cutoff = self.render.throughput_cutoff;
+        scene.samples = self.render.samples;
+        scene.sample_pattern = self.render.sample_pattern;
+        scene.filter = self.render.filter;
+        scene.adaptive_threshold = self.render.adaptive_threshold;
+        scene.shading_model = self.render.shading_model;
+        scene.ambient_occlusion = self.render.ambient_occlusion;
+        scene.render_mode = self.render.mode;
+        scene.fog = self.fog;
+        if let Some(background) = self.background {
+            let (background, environment_lighting) = background.into_background()?;
+            scene.background = background;
+            scene.environment_lighting = environment_lighting;
+        }
+        scene.tone_mapping = self.render.tone_mapping;
+        scene.exposure = self.render.exposure;
+        Ok(scene)
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(std::io::Error),
+    // Malformed file, with the line and column the parser stopped at
+    Parse {
+        line: usize,
+        col: usize,
+        message: String,
+    },
+    // Well formed, but a value is out of range
+    Invalid {
+        field: String,
+        message: String,
+    },
+    // A file the scene refers to, like an image, couldn't be loaded
+    Load {
+        path: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneFileError::Io(e) => write!(f, "could not read scene file: {}", e),
+            SceneFileError::Parse { line, col, message } => write!(f, "line {}, column {}: {}", line, col, message),
+            SceneFileError::Invalid { field, message } => write!(f, "invalid value for `{}`: {}", field, message),
+            SceneFileError::Load { path, message } => write!(f, "could not load `{}`: {}", path, message),
+        }
+    }
+}
+
+impl Error for SceneFileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneFileError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SceneFileError {
+    fn from(e: std::io::Error) -> Self {
+        SceneFileError::Io(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for SceneFileError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        SceneFileError::Parse {
+            line: e.position.line,
+            col: e.position.col,
+            message: e.code.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_error_has_line_and_column() {
+        let source = "Scene(\n    objects: [\n        Sphere(centre: (0.0, 0.0, -5.0) radius: 1.0),\n    ],\n)";
+        match SceneDescription::parse(source) {
+            Err(SceneFileError::Parse { line, col, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(col, 41);
+            },
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn invalid_field(source: &str) -> String {
+        match SceneDescription::parse(source) {
+            Err(SceneFileError::Invalid { field, .. }) => field,
+            other => panic!("expected an invalid value, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn out_of_range_materials_are_rejected() {
+        assert_eq!(invalid_field("Scene(objects: [Sphere(centre: (0.0, 0.0, 0.0), radius: 1.0, material: (reflectivity: 1.5))])"), "objects[0].material.reflectivity");
+        assert_eq!(invalid_field("Scene(objects: [Sphere(centre: (0.0, 0.0, 0.0), radius: 1.0, material: (shininess: -1.0))])"), "objects[0].material.shininess");
+        assert_eq!(invalid_field("Scene(objects: [Sphere(centre: (0.0, 0.0, 0.0), radius: 1.0, material: (transparency: 1.0, ior: 0.0))])"), "objects[0].material.ior");
+        assert_eq!(invalid_field("Scene(objects: [HorizontalPlane(y: 0.0, material: (transparency: 2.0))])"), "objects[0].material.transparency");
+        assert_eq!(
+            invalid_field("Scene(objects: [Union(a: HorizontalPlane(y: 0.0), b: HorizontalPlane(y: 1.0, material: (albedo: (r: -1.0, g: 0.0, b: 0.0))))])"),
+            "objects[0].b.material.albedo",
+        );
+    }
+
+    #[test]
+    fn parse_error_message_includes_position() {
+        let error = SceneDescription::parse("Scene(\n    render: (width: 100,, height: 100),\n)").err().unwrap();
+        assert!(error.to_string().starts_with("line 2, column "), "{}", error);
+    }
+}
@@ -0,0 +1,99 @@
+use serde::Deserialize;
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::lighting::Color;
+
+// How rendered colors, which can be brighter than white, are squeezed into what an image can hold
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum ToneMapping {
+    // Anything brighter than white is cut off
+    Clamp,
+    // x/(1 + x), never quite reaches white so highlights keep some detail
+    Reinhard,
+    // Filmic curve with more contrast, Narkowicz's fit of the ACES reference transform
+    Aces,
+}
+
+impl ToneMapping {
+    // exposure is in stops, each one doubles the brightness before mapping
+    pub fn apply(&self, color: Color, exposure: f32) -> Color {
+        let color = color * exposure.exp2();
+        match self {
+            ToneMapping::Clamp => color.map(|x| x.clamp(0.0, 1.0)),
+            ToneMapping::Reinhard => color.map(|x| x.max(0.0)/(1.0 + x.max(0.0))),
+            ToneMapping::Aces => color.map(|x| {
+                let x = x.max(0.0);
+                (x * (2.51 * x + 0.03)/(x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+            }),
+        }
+    }
+}
+
+// For the command line, names are lower case
+impl FromStr for ToneMapping {
+    type Err = UnknownToneMapping;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMapping::Clamp),
+            "reinhard" => Ok(ToneMapping::Reinhard),
+            "aces" => Ok(ToneMapping::Aces),
+            _ => Err(UnknownToneMapping(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownToneMapping(String);
+
+impl fmt::Display for UnknownToneMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown tone mapping `{}`, expected clamp, reinhard or aces", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(tone_mapping: ToneMapping, x: f32, exposure: f32) -> f32 {
+        tone_mapping.apply(Color::new(x, x, x), exposure).r
+    }
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!((value - expected).abs() < 1.0e-4, "expected {}, got {}", expected, value);
+    }
+
+    #[test]
+    fn clamp_cuts_off_out_of_range() {
+        assert_close(map(ToneMapping::Clamp, 0.5, 0.0), 0.5);
+        assert_close(map(ToneMapping::Clamp, 2.0, 0.0), 1.0);
+        assert_close(map(ToneMapping::Clamp, -1.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn reinhard_curve() {
+        assert_close(map(ToneMapping::Reinhard, 0.0, 0.0), 0.0);
+        assert_close(map(ToneMapping::Reinhard, 1.0, 0.0), 0.5);
+        assert_close(map(ToneMapping::Reinhard, 3.0, 0.0), 0.75);
+        assert!(map(ToneMapping::Reinhard, 1.0e6, 0.0) < 1.0);
+    }
+
+    #[test]
+    fn aces_curve() {
+        assert_close(map(ToneMapping::Aces, 0.0, 0.0), 0.0);
+        assert_close(map(ToneMapping::Aces, 1.0, 0.0), 2.54/3.16);
+        assert_close(map(ToneMapping::Aces, 100.0, 0.0), 1.0);
+        // Gets brighter all the way up to white
+        let samples: Vec<f32> = (0..100).map(|i| map(ToneMapping::Aces, i as f32 * 0.1, 0.0)).collect();
+        assert!(samples.windows(2).all(|pair| pair[1] >= pair[0]));
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        assert_close(map(ToneMapping::Reinhard, 0.5, 1.0), 0.5);
+        assert_close(map(ToneMapping::Clamp, 0.5, -1.0), 0.25);
+    }
+}
@@ -0,0 +1,103 @@
+use na::{Point3, Vector3, Similarity3, Translation3, UnitQuaternion};
+
+use crate::objects::Object;
+use crate::material::Material;
+
+// Moves, rotates and uniformly scales any object. Points are taken into the object's own space
+// before asking it for distances, then the results are brought back into world space.
+pub struct Transformed<O: Object> {
+    pub object: O,
+    transform: Similarity3<f32>,    // Object space -> world space
+    inverse: Similarity3<f32>,      // World space -> object space
+}
+
+impl<O: Object> Transformed<O> {
+    pub fn new(object: O, transform: Similarity3<f32>) -> Self {
+        Self {
+            object,
+            transform,
+            inverse: transform.inverse(),
+        }
+    }
+
+    // rotation is euler angles (roll, pitch, yaw) in DEGREES, scale must be positive
+    pub fn from_parts(object: O, translation: Vector3<f32>, rotation: Vector3<f32>, scale: f32) -> Self {
+        let rotation = UnitQuaternion::from_euler_angles(
+            rotation.x.to_radians(),
+            rotation.y.to_radians(),
+            rotation.z.to_radians(),
+        );
+        Self::new(object, Similarity3::from_parts(Translation3::from(translation), rotation, scale))
+    }
+
+    pub fn transform(&self) -> &Similarity3<f32> {
+        &self.transform
+    }
+
+    pub fn set_transform(&mut self, transform: Similarity3<f32>) {
+        self.transform = transform;
+        self.inverse = transform.inverse();
+    }
+}
+
+impl<O: Object> Object for Transformed<O> {
+    fn distance_estimate(&self, point: &Point3<f32>) -> f32 {
+        // Distances in object space are scaled up along with the object
+        self.object.distance_estimate(&(self.inverse * point)) * self.transform.scaling()
+    }
+
+    fn get_normal(&self, point: &Point3<f32>) -> Vector3<f32> {
+        // Uniform scale doesn't change the direction of normals, so only rotate them
+        self.transform.isometry.rotation * self.object.get_normal(&(self.inverse * point))
+    }
+
+    fn get_material_ref(&self) -> &Material {
+        self.object.get_material_ref()
+    }
+
+    fn get_material(&self, point: &Point3<f32>) -> Material {
+        self.object.get_material(&(self.inverse * point))
+    }
+
+    fn get_type_name(&self) -> &'static str {
+        self.object.get_type_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::{Sphere, AxisAlignedCube};
+
+    const TOLERANCE: f32 = 1.0e-4;
+
+    #[test]
+    fn scaled_distances_are_in_world_space() {
+        let sphere = Sphere { centre: Point3::origin(), radius: 1.0, material: Material::default() };
+        let scaled = Transformed::from_parts(sphere, Vector3::zeros(), Vector3::zeros(), 2.0);
+        // Radius 2 once scaled, not the 1.5 that the object space distance would give
+        let distance = scaled.distance_estimate(&Point3::new(5.0, 0.0, 0.0));
+        assert!((distance - 3.0).abs() < TOLERANCE, "{}", distance);
+    }
+
+    #[test]
+    fn translated_distances() {
+        let sphere = Sphere { centre: Point3::origin(), radius: 1.0, material: Material::default() };
+        let moved = Transformed::from_parts(sphere, Vector3::new(0.0, 3.0, 0.0), Vector3::zeros(), 1.0);
+        let distance = moved.distance_estimate(&Point3::new(0.0, 0.0, 0.0));
+        assert!((distance - 2.0).abs() < TOLERANCE, "{}", distance);
+    }
+
+    #[test]
+    fn normals_are_rotated() {
+        let cube = AxisAlignedCube { centre: Point3::origin(), size: 1.0, material: Material::default() };
+        let rotated = Transformed::from_parts(cube, Vector3::zeros(), Vector3::new(0.0, 0.0, 45.0), 1.0);
+
+        // Turned so an edge points along x, out to sqrt(2)
+        let point = Point3::new(1.5, 0.0, 0.0);
+        let distance = rotated.distance_estimate(&point);
+        assert!((distance - (1.5 - 2.0_f32.sqrt())).abs() < TOLERANCE, "{}", distance);
+        let normal = rotated.get_normal(&point);
+        assert!((normal - Vector3::new(1.0, 0.0, 0.0)).norm() < TOLERANCE, "{:?}", normal);
+    }
+}